@@ -1,22 +1,84 @@
 use crate::{Id, Label};
+use std::fmt::{Display, Formatter};
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum GraphCommand {
-    DeleteNode { id: Id },
-    ExpandEdge { id: Id, label: Label },
-    InsertAfterNode { id: Id, label: Label },
-    InsertBeforeNode { id: Id, label: Label },
-    InsertNode { label: Label },
-    LinkEdge { from: Id, to: Id },
-    RenameNode { id: Id, label: Label },
-    SelectNode { id: Id },
-    SetDirection { is_left_right: bool },
-    UnlinkEdge { id: Id },
+    CollapseNode {
+        id: Id,
+    },
+    DeleteNode {
+        id: Id,
+    },
+    ExpandEdge {
+        id: Id,
+        label: Label,
+    },
+    InsertAfterNode {
+        id: Id,
+        label: Label,
+    },
+    InsertBeforeNode {
+        id: Id,
+        label: Label,
+    },
+    InsertNode {
+        label: Label,
+    },
+    LabelEdge {
+        id: Id,
+        label: Option<Label>,
+    },
+    LinkEdge {
+        from: Id,
+        to: Id,
+        label: Option<Label>,
+    },
+    MergeNodes {
+        from: Id,
+        into: Id,
+        join_labels: bool,
+    },
+    Redo,
+    RenameNode {
+        id: Id,
+        label: Label,
+    },
+    RetargetEdge {
+        id: Id,
+        from: Id,
+        to: Id,
+    },
+    ReverseEdge {
+        id: Id,
+    },
+    SelectNode {
+        id: Id,
+    },
+    SetDirection {
+        is_left_right: bool,
+    },
+    SetLegend {
+        show_legend: bool,
+    },
+    SetPalette {
+        name: String,
+    },
+    SetStrictDag {
+        is_strict_dag: bool,
+    },
+    Undo,
+    UnlinkEdge {
+        id: Id,
+    },
 }
 
 impl GraphCommand {
     pub fn to_help_string(&self) -> String {
         match self {
+            GraphCommand::CollapseNode { id } => format!(
+                "Collapse the <{}> node, linking everything before it to everything after it",
+                id
+            ),
             GraphCommand::DeleteNode { id } => format!("Delete the <{}> node", id),
             GraphCommand::ExpandEdge { id, label } => format!(
                 "Expand the <{}> edge with a new node labelled \"{}\"",
@@ -33,12 +95,42 @@ impl GraphCommand {
             GraphCommand::InsertNode { label } => {
                 format!("Insert a node labelled \"{}\" into the graph", label)
             }
-            GraphCommand::LinkEdge { from, to } => {
-                format!("Link the <{}> node to the <{}> node", from, to)
+            GraphCommand::LabelEdge { id, label } => match label {
+                Some(label) => format!("Label the <{}> edge \"{}\"", id, label),
+                None => format!("Clear the label on the <{}> edge", id),
+            },
+            GraphCommand::LinkEdge { from, to, label } => match label {
+                Some(label) => format!(
+                    "Link the <{}> node to the <{}> node, labelled \"{}\"",
+                    from, to, label
+                ),
+                None => format!("Link the <{}> node to the <{}> node", from, to),
+            },
+            GraphCommand::MergeNodes {
+                from,
+                into,
+                join_labels,
+            } => {
+                if *join_labels {
+                    format!(
+                        "Merge the <{}> node into the <{}> node, joining their labels",
+                        from, into
+                    )
+                } else {
+                    format!("Merge the <{}> node into the <{}> node", from, into)
+                }
             }
+            GraphCommand::Redo => "Redo the last change that was undone".into(),
             GraphCommand::RenameNode { id, label } => {
                 format!("Rename the <{}> node to \"{}\"", id, label)
             }
+            GraphCommand::RetargetEdge { id, from, to } => format!(
+                "Move the <{}> edge so it links the <{}> node to the <{}> node",
+                id, from, to
+            ),
+            GraphCommand::ReverseEdge { id } => {
+                format!("Reverse the direction of the <{}> edge", id)
+            }
             GraphCommand::SelectNode { id } => format!("Select the <{}> node and highlight it", id),
             GraphCommand::SetDirection { is_left_right } => format!(
                 "Change the orientation of the graph to {}",
@@ -48,7 +140,53 @@ impl GraphCommand {
                     "top to bottom"
                 }
             ),
+            GraphCommand::SetLegend { show_legend } => {
+                if *show_legend {
+                    "Draw a key to the hashtag colours and how many nodes use each".into()
+                } else {
+                    "Stop drawing the key to the hashtag colours".into()
+                }
+            }
+            GraphCommand::SetPalette { name } => {
+                format!("Colour hashtags from the <{}> palette", name)
+            }
+            GraphCommand::SetStrictDag { is_strict_dag } => {
+                if *is_strict_dag {
+                    "Refuse edges which would make a cycle, a self loop or a duplicate".into()
+                } else {
+                    "Allow cycles, self loops and duplicate edges again".into()
+                }
+            }
+            GraphCommand::Undo => "Undo the last change to the graph".into(),
             GraphCommand::UnlinkEdge { id } => format!("Unlink the <{}> edge", id),
         }
     }
 }
+
+/// What a `GraphCommand` did: a message for people, and the nodes and edges it touched for
+/// anything which needs to react to the change precisely.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct CommandOutcome {
+    pub message: String,
+    pub created_nodes: Vec<Id>,
+    pub removed_nodes: Vec<Id>,
+    pub changed_nodes: Vec<Id>,
+    pub created_edges: Vec<Id>,
+    pub removed_edges: Vec<Id>,
+    pub changed_edges: Vec<Id>,
+}
+
+impl CommandOutcome {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        CommandOutcome {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for CommandOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
@@ -0,0 +1,60 @@
+use crate::Id;
+use std::fmt::{Display, Formatter};
+
+/// Why a `GraphCommand` couldn't be carried out. The graph is left unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    NodeNotFound(Id),
+    EdgeNotFound(Id),
+    SourceNodeNotFound(Id),
+    TargetNodeNotFound(Id),
+    DuplicateNodeId(Id),
+    DuplicateEdgeId(Id),
+    MergeIntoItself(Id),
+    SelfLoop(Id),
+    DuplicateEdge {
+        from: Id,
+        to: Id,
+        existing: Id,
+    },
+    /// the nodes around the cycle the new edge would close, starting and ending at its source.
+    WouldCreateCycle {
+        cycle: Vec<Id>,
+    },
+    NothingToUndo,
+    NothingToRedo,
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::NodeNotFound(id) => write!(f, "node {} not found", id),
+            GraphError::EdgeNotFound(id) => write!(f, "edge {} not found", id),
+            GraphError::SourceNodeNotFound(id) => write!(f, "source node {} not found", id),
+            GraphError::TargetNodeNotFound(id) => write!(f, "target node {} not found", id),
+            GraphError::DuplicateNodeId(id) => write!(f, "node {} already exists", id),
+            GraphError::DuplicateEdgeId(id) => write!(f, "edge {} already exists", id),
+            GraphError::MergeIntoItself(id) => write!(f, "can't merge {} into itself", id),
+            GraphError::SelfLoop(id) => {
+                write!(f, "can't link {} to itself in strict DAG mode", id)
+            }
+            GraphError::DuplicateEdge { from, to, existing } => write!(
+                f,
+                "{} is already linked to {} by {} in strict DAG mode",
+                from, to, existing
+            ),
+            GraphError::WouldCreateCycle { cycle } => {
+                let cycle: Vec<_> = cycle.iter().map(|id| id.to_string()).collect();
+                write!(
+                    f,
+                    "can't make the cycle {} in strict DAG mode",
+                    cycle.join(" -> ")
+                )
+            }
+            GraphError::NothingToUndo => write!(f, "nothing to undo"),
+            GraphError::NothingToRedo => write!(f, "nothing to redo"),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
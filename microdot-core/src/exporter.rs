@@ -4,13 +4,44 @@ use crate::{Id, Label};
 pub enum NodeHighlight {
     Normal,
     SearchResult,
+    /// upstream or downstream of the node being looked at.
+    Reachable,
+    /// on a path between two nodes.
+    OnPath,
     CurrentNode,
 }
 
+#[derive(Copy, Clone)]
+pub enum EdgeHighlight {
+    Normal,
+    /// on a path between two nodes.
+    OnPath,
+}
+
+/// where a node sits in the flow of the graph. In a current reality tree the roots are the root
+/// causes, and the leaves the undesirable effects they lead to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NodeRole {
+    /// edges leave it, but none arrive
+    Root,
+    Intermediate,
+    /// edges arrive, but none leave
+    Leaf,
+    /// not linked to anything
+    Isolated,
+}
+
 pub trait Exporter {
     fn set_direction(&mut self, is_left_right: bool);
 
-    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight);
+    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight, role: NodeRole);
 
-    fn add_edge(&mut self, id: &Id, from: &Id, to: &Id);
+    fn add_edge(
+        &mut self,
+        id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        highlight: EdgeHighlight,
+    );
 }
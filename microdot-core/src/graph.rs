@@ -1,6 +1,12 @@
-use crate::command::GraphCommand;
-use crate::exporter::{Exporter, NodeHighlight};
+use crate::command::{CommandOutcome, GraphCommand};
+use crate::error::GraphError;
+use crate::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
+use crate::hash::{extract_hashtags, HashTag};
+use crate::history::History;
 use crate::{CommandResult, Id, Label};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
 
 #[derive(Default)]
 pub struct Graph {
@@ -9,19 +15,172 @@ pub struct Graph {
     nodes: Vec<Node>,
     edges: Vec<Edge>,
     is_left_right: bool,
-    current_search: Option<Label>,
+    /// when set, edges which would make a cycle, a self-loop or a duplicate are refused.
+    is_strict_dag: bool,
+    /// the name of the palette hashtags are coloured from, if not the default one.
+    palette: Option<String>,
+    /// when set, drawings get a key to the hashtags' colours.
+    show_legend: bool,
+    current_query: Option<Query>,
     current_node: Option<Id>,
+    /// limits the nodes `export` shows; it's a way of looking at the graph, so undo leaves it be.
+    filter: Option<Filter>,
+    history: History<Snapshot>,
 }
 
+/// what the last search or query picked out. It's worked out again on each export, so the
+/// highlighting keeps up with later edits.
+enum Query {
+    Search(Label),
+    Reach(Id, Reach),
+    Paths { from: Id, to: Id, count: PathCount },
+}
+
+/// whether to look for just one shortest path between two nodes, or every one.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathCount {
+    Shortest,
+    All,
+}
+
+/// which way to follow edges from a node: back to its causes, or on to its effects.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Reach {
+    Upstream,
+    Downstream,
+}
+
+impl Display for Reach {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reach::Upstream => write!(f, "upstream"),
+            Reach::Downstream => write!(f, "downstream"),
+        }
+    }
+}
+
+/// a set of nodes to pick out of a graph.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Selection {
+    Nodes(Vec<Id>),
+    /// every node tagged with this hashtag, eg `#sales`
+    HashTag(String),
+    /// a node and everything up or downstream of it.
+    Reach(Id, Reach),
+}
+
+/// a test of a node's hashtags, like `#Frodo and not #Ring`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TagExpr {
+    /// tagged with this hashtag, given without the '#'
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Box<TagExpr>, Box<TagExpr>),
+    Or(Box<TagExpr>, Box<TagExpr>),
+}
+
+impl TagExpr {
+    /// whether a node with these hashtags, each starting with '#', passes.
+    pub fn matches(&self, hash_tags: &[String]) -> bool {
+        match self {
+            TagExpr::Tag(tag) => hash_tags.iter().any(|t| t[1..] == *tag),
+            TagExpr::Not(expr) => !expr.matches(hash_tags),
+            TagExpr::And(lhs, rhs) => lhs.matches(hash_tags) && rhs.matches(hash_tags),
+            TagExpr::Or(lhs, rhs) => lhs.matches(hash_tags) || rhs.matches(hash_tags),
+        }
+    }
+
+    /// how tightly it binds, for putting brackets back when it's written out.
+    fn precedence(&self) -> u8 {
+        match self {
+            TagExpr::Or(_, _) => 0,
+            TagExpr::And(_, _) => 1,
+            TagExpr::Not(_) | TagExpr::Tag(_) => 2,
+        }
+    }
+
+    fn fmt_inner(&self, f: &mut Formatter<'_>, precedence: u8) -> std::fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "(")?;
+            self.fmt_inner(f, 0)?;
+            return write!(f, ")");
+        }
+
+        match self {
+            TagExpr::Tag(tag) => write!(f, "#{}", tag),
+            TagExpr::Not(expr) => {
+                write!(f, "not ")?;
+                expr.fmt_inner(f, 2)
+            }
+            TagExpr::And(lhs, rhs) => {
+                lhs.fmt_inner(f, 1)?;
+                write!(f, " and ")?;
+                rhs.fmt_inner(f, 2)
+            }
+            TagExpr::Or(lhs, rhs) => {
+                lhs.fmt_inner(f, 0)?;
+                write!(f, " or ")?;
+                rhs.fmt_inner(f, 1)
+            }
+        }
+    }
+}
+
+impl Display for TagExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_inner(f, 0)
+    }
+}
+
+/// which nodes `Graph::export` shows; the rest of the graph is still there, just hidden.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Filter {
+    pub expr: TagExpr,
+    /// also show the nodes directly linked to the ones which match.
+    pub with_neighbours: bool,
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.with_neighbours {
+            write!(f, " with neighbours")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 struct Node {
     id: Id,
     label: Label,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 struct Edge {
     id: Id,
     from: Id,
     to: Id,
+    #[serde(default)]
+    label: Option<Label>,
+}
+
+/// Everything a `GraphCommand` can change; undo and redo swap these in and out wholesale, so
+/// cascading changes like the edges removed by `DeleteNode` come back with the node. How the
+/// graph is being looked at, like the selection and the direction, isn't part of it, so undo
+/// only takes back edits.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Snapshot {
+    node_high_water: usize,
+    edge_high_water: usize,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    #[serde(default)]
+    is_strict_dag: bool,
+    #[serde(default)]
+    palette: Option<String>,
+    #[serde(default)]
+    show_legend: bool,
 }
 
 impl Graph {
@@ -45,6 +204,29 @@ impl Graph {
         Id::new(id)
     }
 
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn node_high_water(&self) -> usize {
+        self.node_high_water
+    }
+
+    pub fn edge_high_water(&self) -> usize {
+        self.edge_high_water
+    }
+
+    /// make sure ids up to these marks are never handed out again, eg after nodes and edges with
+    /// those ids have been deleted and the graph saved.
+    pub fn raise_high_water_marks(&mut self, node_high_water: usize, edge_high_water: usize) {
+        self.node_high_water = self.node_high_water.max(node_high_water);
+        self.edge_high_water = self.edge_high_water.max(edge_high_water);
+    }
+
     fn find_edge_idx(&self, id: &Id) -> Option<usize> {
         self.edges
             .iter()
@@ -61,46 +243,376 @@ impl Graph {
             .map(|(idx, _)| idx)
     }
 
+    /// send the graph to an exporter, leaving out anything the filter hides.
     pub fn export<X: Exporter>(&self, exporter: &mut X) {
+        self.export_nodes(exporter, self.filtered_nodes().as_ref());
+    }
+
+    /// send the whole graph to an exporter, filter or not, as when saving it.
+    pub fn export_all<X: Exporter>(&self, exporter: &mut X) {
+        self.export_nodes(exporter, None);
+    }
+
+    /// export just the `visible` nodes and the edges between them, or everything if that's None.
+    fn export_nodes<X: Exporter>(&self, exporter: &mut X, visible: Option<&HashSet<Id>>) {
         exporter.set_direction(self.is_left_right);
 
-        for node in &self.nodes {
-            let highlight = if self.node_matches_current_search(node) {
-                NodeHighlight::SearchResult
+        let (node_highlight, matched_nodes, matched_edges) = self.query_results();
+        let is_visible = |id: &Id| visible.is_none_or(|visible| visible.contains(id));
+
+        // roles come from the whole graph, so hiding a cause doesn't make its effect a root
+        for (node, role) in self.nodes.iter().zip(self.node_roles()) {
+            if !is_visible(&node.id) {
+                continue;
+            }
+
+            let highlight = if matched_nodes.contains(&node.id) {
+                node_highlight
             } else if self.current_node == Some(node.id.clone()) {
                 NodeHighlight::CurrentNode
             } else {
                 NodeHighlight::Normal
             };
 
-            exporter.add_node(&node.id, &node.label, highlight);
+            exporter.add_node(&node.id, &node.label, highlight, role);
         }
 
         for edge in &self.edges {
-            exporter.add_edge(&edge.id, &edge.from, &edge.to);
+            if !is_visible(&edge.from) || !is_visible(&edge.to) {
+                continue;
+            }
+
+            let highlight = if matched_edges.contains(&edge.id) {
+                EdgeHighlight::OnPath
+            } else {
+                EdgeHighlight::Normal
+            };
+
+            exporter.add_edge(
+                &edge.id,
+                &edge.from,
+                &edge.to,
+                edge.label.as_ref(),
+                highlight,
+            );
+        }
+    }
+
+    /// the nodes the filter lets through, or None when there isn't one.
+    fn filtered_nodes(&self) -> Option<HashSet<Id>> {
+        let filter = self.filter.as_ref()?;
+
+        let mut visible: HashSet<Id> = self
+            .nodes
+            .iter()
+            .filter(|n| {
+                let (hash_tags, _) = extract_hashtags(&n.label.0);
+                let hash_tags: Vec<_> = hash_tags.iter().map(|t| t.to_string()).collect();
+                filter.expr.matches(&hash_tags)
+            })
+            .map(|n| n.id.clone())
+            .collect();
+
+        if filter.with_neighbours {
+            let neighbours: Vec<_> = self
+                .edges
+                .iter()
+                .filter_map(|e| {
+                    if visible.contains(&e.from) {
+                        Some(e.to.clone())
+                    } else if visible.contains(&e.to) {
+                        Some(e.from.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            visible.extend(neighbours);
+        }
+
+        Some(visible)
+    }
+
+    /// show only the nodes which pass `filter` when exporting, or everything again with None.
+    pub fn set_filter(&mut self, filter: Option<Filter>) -> CommandResult {
+        self.filter = filter;
+
+        let msg = match (&self.filter, self.filtered_nodes()) {
+            (Some(filter), Some(visible)) => format!(
+                "showing {} of {} nodes, for {}",
+                visible.len(),
+                self.nodes.len(),
+                filter
+            ),
+            _ => format!("filter off, showing all {} nodes", self.nodes.len()),
+        };
+
+        CommandResult::new(msg)
+    }
+
+    pub fn filter(&self) -> Option<&Filter> {
+        self.filter.as_ref()
+    }
+
+    /// the nodes and edges picked out by the current query, and how to highlight the nodes.
+    fn query_results(&self) -> (NodeHighlight, HashSet<Id>, HashSet<Id>) {
+        // nodes named in a query may have been deleted since; then there's nothing to show.
+        match &self.current_query {
+            Some(Query::Search(sub_label)) => (
+                NodeHighlight::SearchResult,
+                self.nodes
+                    .iter()
+                    .filter(|n| node_matches_search(n, sub_label))
+                    .map(|n| n.id.clone())
+                    .collect(),
+                HashSet::new(),
+            ),
+            Some(Query::Reach(id, reach)) => (
+                NodeHighlight::Reachable,
+                self.find_reachable(id, *reach)
+                    .map(|reached| reached.into_iter().map(|(id, _)| id).collect())
+                    .unwrap_or_default(),
+                HashSet::new(),
+            ),
+            Some(Query::Paths { from, to, count }) => {
+                let paths = self.find_paths(from, to, *count).unwrap_or_default();
+                let nodes = paths
+                    .iter()
+                    .flat_map(|path| self.nodes_along(from, path))
+                    .collect();
+                let edges = paths.into_iter().flatten().collect();
+                (NodeHighlight::OnPath, nodes, edges)
+            }
+            None => (NodeHighlight::Normal, HashSet::new(), HashSet::new()),
         }
     }
 
-    pub fn apply_command(&mut self, command: GraphCommand) -> CommandResult {
+    pub fn apply_command(&mut self, command: GraphCommand) -> Result<CommandOutcome, GraphError> {
+        match command {
+            GraphCommand::Undo => return self.undo(),
+            GraphCommand::Redo => return self.redo(),
+            _ => {}
+        }
+
+        let description = command.to_help_string();
+        let before = self.snapshot();
+        let result = self.apply_undoable_command(command);
+
+        // commands which fail or change nothing shouldn't need undoing
+        if result.is_ok() && self.snapshot() != before {
+            self.history.record(description, before);
+        }
+
+        result
+    }
+
+    fn apply_undoable_command(
+        &mut self,
+        command: GraphCommand,
+    ) -> Result<CommandOutcome, GraphError> {
         match command {
+            GraphCommand::CollapseNode { id } => self.collapse_node(&id),
             GraphCommand::DeleteNode { id } => self.delete_node(&id),
             GraphCommand::ExpandEdge { id, label } => self.expand_edge(&id, &label),
             GraphCommand::InsertAfterNode { id, label } => self.inject_after_node(&id, &label),
             GraphCommand::InsertBeforeNode { id, label } => self.inject_before_node(&id, &label),
-            GraphCommand::InsertNode { label } => self.insert_node(label).1,
-            GraphCommand::LinkEdge { from, to } => self.link_edge(&from, &to),
+            GraphCommand::InsertNode { label } => Ok(self.insert_node(label).1),
+            GraphCommand::LabelEdge { id, label } => self.label_edge(&id, label),
+            GraphCommand::LinkEdge { from, to, label } => self.link_edge(&from, &to, label),
+            GraphCommand::MergeNodes {
+                from,
+                into,
+                join_labels,
+            } => self.merge_nodes(&from, &into, join_labels),
             GraphCommand::RenameNode { id, label } => self.rename_node(&id, label),
+            GraphCommand::RetargetEdge { id, from, to } => self.retarget_edge(&id, &from, &to),
+            GraphCommand::ReverseEdge { id } => self.reverse_edge(&id),
             GraphCommand::SelectNode { id } => self.select_node(&id),
-            GraphCommand::SetDirection { is_left_right } => self.set_direction(is_left_right),
+            GraphCommand::SetDirection { is_left_right } => Ok(self.set_direction(is_left_right)),
+            GraphCommand::SetLegend { show_legend } => Ok(self.set_show_legend(show_legend)),
+            GraphCommand::SetPalette { name } => Ok(self.set_palette(name)),
+            GraphCommand::SetStrictDag { is_strict_dag } => Ok(self.set_strict_dag(is_strict_dag)),
             GraphCommand::UnlinkEdge { id } => self.unlink_edge(&id),
+            GraphCommand::Undo | GraphCommand::Redo => {
+                unreachable!("undo and redo are handled by apply_command")
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            node_high_water: self.node_high_water,
+            edge_high_water: self.edge_high_water,
+            nodes: self.nodes.clone(),
+            edges: self.edges.clone(),
+            is_strict_dag: self.is_strict_dag,
+            palette: self.palette.clone(),
+            show_legend: self.show_legend,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        let Snapshot {
+            node_high_water,
+            edge_high_water,
+            nodes,
+            edges,
+            is_strict_dag,
+            palette,
+            show_legend,
+        } = snapshot;
+
+        self.node_high_water = node_high_water;
+        self.edge_high_water = edge_high_water;
+        self.nodes = nodes;
+        self.edges = edges;
+        self.is_strict_dag = is_strict_dag;
+        self.palette = palette;
+        self.show_legend = show_legend;
+
+        // the selection stays put, unless its node has gone
+        if let Some(id) = &self.current_node {
+            if self.find_node_idx(id).is_none() {
+                self.current_node = None;
+            }
         }
     }
 
-    fn node_matches_current_search(&self, n: &Node) -> bool {
-        match self.current_search.as_ref() {
-            Some(current_search) => n.label.0.contains(&current_search.0),
-            None => false,
+    fn undo(&mut self) -> Result<CommandOutcome, GraphError> {
+        let current = self.snapshot();
+        let entry = self
+            .history
+            .undo(current.clone())
+            .ok_or(GraphError::NothingToUndo)?;
+        self.restore(entry.state);
+        Ok(diff_snapshots(
+            &current,
+            &self.snapshot(),
+            format!("undone: {}", entry.description),
+        ))
+    }
+
+    fn redo(&mut self) -> Result<CommandOutcome, GraphError> {
+        let current = self.snapshot();
+        let entry = self
+            .history
+            .redo(current.clone())
+            .ok_or(GraphError::NothingToRedo)?;
+        self.restore(entry.state);
+        Ok(diff_snapshots(
+            &current,
+            &self.snapshot(),
+            format!("redone: {}", entry.description),
+        ))
+    }
+
+    /// swap the whole graph for `other`, as when importing a file; it can be undone like any
+    /// other change.
+    pub fn replace<S: Into<String>>(&mut self, other: Graph, description: S) -> CommandOutcome {
+        let before = self.snapshot();
+        self.restore(other.snapshot());
+        self.is_left_right = other.is_left_right;
+        self.current_node = other.current_node.clone();
+        self.current_query = None;
+
+        let message = format!(
+            "replaced graph with {} nodes and {} edges",
+            self.nodes.len(),
+            self.edges.len()
+        );
+        let outcome = diff_snapshots(&before, &self.snapshot(), message);
+        self.history.record(description, before);
+        outcome
+    }
+
+    /// bring the nodes and edges of `other` into this graph with fresh ids, as one change which
+    /// can be undone, returning each of its node ids alongside the id it has here now.
+    ///
+    /// With `unify_labels`, a node labelled the same as one which is already here becomes that
+    /// node, and edges which are then already here aren't added again. If any edge is refused,
+    /// as it may be in strict DAG mode, nothing is merged.
+    pub fn merge<S: Into<String>>(
+        &mut self,
+        other: &Graph,
+        unify_labels: bool,
+        description: S,
+    ) -> Result<(CommandOutcome, Vec<(Id, Id)>), GraphError> {
+        let before = self.snapshot();
+        let current_node = self.current_node.clone();
+
+        let mut ids = vec![];
+        let mut inserted = 0;
+        for node in &other.nodes {
+            let existing = if unify_labels {
+                self.nodes.iter().find(|n| n.label == node.label)
+            } else {
+                None
+            };
+            let id = match existing {
+                Some(existing) => existing.id.clone(),
+                None => {
+                    inserted += 1;
+                    self.insert_node(node.label.clone()).0
+                }
+            };
+            ids.push((node.id.clone(), id));
+        }
+
+        let new_ids: HashMap<&Id, &Id> = ids.iter().map(|(old, new)| (old, new)).collect();
+        let mut linked = 0;
+        for edge in &other.edges {
+            let (from, to) = (new_ids[&edge.from], new_ids[&edge.to]);
+            let is_already_here = self
+                .edges
+                .iter()
+                .any(|e| &e.from == from && &e.to == to && e.label == edge.label);
+            if unify_labels && is_already_here {
+                continue;
+            }
+
+            if let Err(e) = self.link_edge(from, to, edge.label.clone()) {
+                self.restore(before);
+                self.current_node = current_node;
+                return Err(e);
+            }
+            linked += 1;
+        }
+
+        // inserting moves the selection, but merging shouldn't
+        self.current_node = current_node;
+
+        let message = if unify_labels {
+            format!(
+                "merged {} nodes, {} of them matched by label, and {} edges",
+                other.nodes.len(),
+                other.nodes.len() - inserted,
+                linked
+            )
+        } else {
+            format!("merged {} nodes and {} edges", inserted, linked)
+        };
+        let outcome = diff_snapshots(&before, &self.snapshot(), message);
+        if self.snapshot() != before {
+            self.history.record(description, before);
         }
+
+        Ok((outcome, ids))
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+
+    /// the undo and redo stacks as json, so they can be saved alongside the graph.
+    pub fn export_history(&self) -> String {
+        serde_json::to_string(&self.history).expect("could not serialise history")
+    }
+
+    pub fn import_history(&mut self, json: &str) -> Result<(), serde_json::Error> {
+        let limit = self.history.limit();
+        self.history = serde_json::from_str(json)?;
+        self.history.set_limit(limit);
+        Ok(())
     }
 
     pub fn find_node_label(&self, id: &Id) -> Option<Label> {
@@ -114,12 +626,10 @@ impl Graph {
     }
 
     pub fn highlight_search_results(&mut self, sub_label: Label) -> CommandResult {
-        self.current_search = Some(sub_label.clone());
-
         let mut matches: Vec<_> = self
             .nodes
             .iter()
-            .filter(|n| self.node_matches_current_search(n))
+            .filter(|n| node_matches_search(n, &sub_label))
             .collect();
 
         matches.sort_by_key(|n| &n.id.0);
@@ -135,168 +645,1110 @@ impl Graph {
             search_lines.join("\n")
         );
 
-        CommandResult::new(msg)
-    }
+        self.current_query = Some(Query::Search(sub_label));
 
-    pub fn set_direction(&mut self, is_left_right: bool) -> CommandResult {
-        self.is_left_right = is_left_right;
-        CommandResult::new(format!(
-            "Direction changed to {}",
-            if is_left_right { "LR" } else { "TB" }
-        ))
+        CommandResult::new(msg)
     }
 
-    fn unlink_edge(&mut self, id: &Id) -> CommandResult {
-        match self.find_edge_idx(id) {
-            Some(idx) => {
-                self.edges.remove(idx);
+    /// every node which can be reached from `id` by following edges forwards (downstream) or
+    /// backwards (upstream), with how many edges away it is, nearest first.
+    pub fn find_reachable(&self, id: &Id, reach: Reach) -> Result<Vec<(Id, usize)>, GraphError> {
+        if self.find_node_idx(id).is_none() {
+            return Err(GraphError::NodeNotFound(id.clone()));
+        }
 
-                CommandResult::new(format!("edge {} removed", id))
+        let mut seen: HashSet<&Id> = HashSet::from([id]);
+        let mut queue = VecDeque::from([(id, 0)]);
+        let mut reached = vec![];
+
+        while let Some((current, depth)) = queue.pop_front() {
+            for edge in &self.edges {
+                let next = match reach {
+                    Reach::Downstream if &edge.from == current => &edge.to,
+                    Reach::Upstream if &edge.to == current => &edge.from,
+                    _ => continue,
+                };
+
+                if seen.insert(next) {
+                    reached.push((next.clone(), depth + 1));
+                    queue.push_back((next, depth + 1));
+                }
             }
-            None => CommandResult::new(format!("edge {} not found", id)),
         }
+
+        Ok(reached)
     }
 
-    fn rename_node(&mut self, id: &Id, label: Label) -> CommandResult {
-        if let Some(idx) = self.find_node_idx(id) {
-            self.current_node = Some(id.clone());
+    pub fn highlight_reachable(
+        &mut self,
+        id: &Id,
+        reach: Reach,
+    ) -> Result<CommandResult, GraphError> {
+        let reached = self.find_reachable(id, reach)?;
 
-            if let Some(node) = self.nodes.get_mut(idx) {
-                node.label = label.clone();
+        self.current_query = Some(Query::Reach(id.clone(), reach));
 
-                CommandResult::new(format!("Node {} renamed to '{}'", id, label))
-            } else {
-                CommandResult::new(format!("Could not find node at index {}", idx))
-            }
-        } else {
-            CommandResult::new(format!("Could not find node {}", id))
+        if reached.is_empty() {
+            return Ok(CommandResult::new(format!(
+                "nothing is {} of {}",
+                reach, id
+            )));
         }
+
+        let lines: Vec<_> = reached
+            .iter()
+            .map(|(id, depth)| {
+                let label = self.find_node_label(id).map(|l| l.0).unwrap_or_default();
+                format!("{}: {} (depth {})", id, label, depth)
+            })
+            .collect();
+
+        Ok(CommandResult::new(format!(
+            "{} of {},\n{}\n",
+            reach,
+            id,
+            lines.join("\n")
+        )))
     }
 
-    pub fn insert_node(&mut self, label: Label) -> (Id, CommandResult) {
-        let id = self.next_node_id();
+    /// the paths from `from` to `to`, each as the ids of the edges along it, shortest first. All
+    /// simple paths stops after `MAX_PATHS`, since there can be exponentially many.
+    pub fn find_paths(
+        &self,
+        from: &Id,
+        to: &Id,
+        count: PathCount,
+    ) -> Result<Vec<Vec<Id>>, GraphError> {
+        if self.find_node_idx(from).is_none() {
+            return Err(GraphError::SourceNodeNotFound(from.clone()));
+        }
+        if self.find_node_idx(to).is_none() {
+            return Err(GraphError::TargetNodeNotFound(to.clone()));
+        }
 
-        let node = Node {
-            id: id.clone(),
-            label: label.clone(),
-        };
+        Ok(match count {
+            PathCount::Shortest => self.find_shortest_path(from, to).into_iter().collect(),
+            PathCount::All => self.find_all_paths(from, to),
+        })
+    }
 
-        self.nodes.push(node);
-        self.current_node = Some(id.clone());
+    pub fn highlight_paths(
+        &mut self,
+        from: &Id,
+        to: &Id,
+        count: PathCount,
+    ) -> Result<CommandResult, GraphError> {
+        let paths = self.find_paths(from, to, count)?;
+
+        self.current_query = Some(Query::Paths {
+            from: from.clone(),
+            to: to.clone(),
+            count,
+        });
+
+        let msg = match (count, paths.len()) {
+            (_, 0) => {
+                return Ok(CommandResult::new(format!(
+                    "no path from {} to {}",
+                    from, to
+                )))
+            }
+            (PathCount::Shortest, _) => format!(
+                "shortest path from {} to {},\n{}\n",
+                from,
+                to,
+                self.describe_path(from, &paths[0])
+            ),
+            (PathCount::All, n) => {
+                let lines: Vec<_> = paths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, path)| format!("{}: {}", i + 1, self.describe_path(from, path)))
+                    .collect();
+                let capped = if n >= MAX_PATHS {
+                    format!(" (stopped after {})", MAX_PATHS)
+                } else {
+                    "".into()
+                };
+                format!(
+                    "{} path(s) from {} to {}{},\n{}\n",
+                    n,
+                    from,
+                    to,
+                    capped,
+                    lines.join("\n")
+                )
+            }
+        };
 
-        (
-            id.clone(),
-            CommandResult::new(format!("inserted node {}: '{}'", id, label)),
-        )
+        Ok(CommandResult::new(msg))
     }
 
-    pub fn select_node(&mut self, id: &Id) -> CommandResult {
-        if self.find_node_idx(id).is_none() {
-            return CommandResult::new(format!("node {} not found", id));
+    /// a path as a readable chain, eg `late (n3) --causes--> angry (n5) --> leaves (n27)`
+    fn describe_path(&self, from: &Id, path: &[Id]) -> String {
+        let describe_node = |id: &Id| match self.find_node_label(id) {
+            Some(label) => format!("{} ({})", label, id),
+            None => id.to_string(),
+        };
+
+        let mut chain = describe_node(from);
+        for edge in path.iter().filter_map(|id| self.find_edge_idx(id)) {
+            let edge = &self.edges[edge];
+            match &edge.label {
+                Some(label) => chain.push_str(&format!(" --{}--> ", label)),
+                None => chain.push_str(" --> "),
+            }
+            chain.push_str(&describe_node(&edge.to));
         }
+        chain
+    }
 
-        self.current_node = Some(id.clone());
-        CommandResult::new(format!("node {} selected", id))
+    /// the nodes visited along a path of edges, starting at `from`.
+    fn nodes_along(&self, from: &Id, path: &[Id]) -> Vec<Id> {
+        let mut nodes = vec![from.clone()];
+        nodes.extend(
+            path.iter()
+                .filter_map(|id| self.find_edge_idx(id))
+                .map(|idx| self.edges[idx].to.clone()),
+        );
+        nodes
     }
 
-    pub fn inject_after_node(&mut self, from: &Id, label: &Label) -> CommandResult {
-        if self.find_node_idx(from).is_none() {
-            return CommandResult::new(format!("source node {} not found", from));
-        }
+    fn outgoing<'a>(&'a self, id: &'a Id) -> impl Iterator<Item = &'a Edge> + 'a {
+        self.edges.iter().filter(move |e| &e.from == id)
+    }
 
-        let (id, _) = self.insert_node(label.clone());
+    /// every path from `from` to `to` which doesn't visit a node twice, as edge ids.
+    fn find_all_paths(&self, from: &Id, to: &Id) -> Vec<Vec<Id>> {
+        if from == to {
+            return vec![vec![]];
+        }
 
-        self.link_edge(from, &id);
-        self.current_node = Some(id.clone());
+        let mut paths = vec![];
+        let mut visited = vec![from];
+        let mut path: Vec<&Edge> = vec![];
+        let mut stack = vec![self.outgoing(from)];
+
+        while let Some(next) = stack.last_mut() {
+            match next.next() {
+                Some(edge) if &edge.to == to => {
+                    paths.push(path.iter().chain([&edge]).map(|e| e.id.clone()).collect());
+                    if paths.len() >= MAX_PATHS {
+                        break;
+                    }
+                }
+                Some(edge) if !visited.contains(&&edge.to) => {
+                    visited.push(&edge.to);
+                    path.push(edge);
+                    stack.push(self.outgoing(&edge.to));
+                }
+                Some(_) => {}
+                None => {
+                    stack.pop();
+                    visited.pop();
+                    path.pop();
+                }
+            }
+        }
 
-        CommandResult::new(format!("inserted node {}: '{}' after {}", id, label, from))
+        paths.sort_by_key(|path: &Vec<Id>| path.len());
+        paths
     }
 
-    pub fn inject_before_node(&mut self, to: &Id, label: &Label) -> CommandResult {
-        if self.find_node_idx(to).is_none() {
-            return CommandResult::new(format!("target node {} not found", to));
+    /// the ids of the nodes in `selection`.
+    pub fn select(&self, selection: &Selection) -> Result<HashSet<Id>, GraphError> {
+        match selection {
+            Selection::Nodes(ids) => ids
+                .iter()
+                .map(|id| match self.find_node_idx(id) {
+                    Some(_) => Ok(id.clone()),
+                    None => Err(GraphError::NodeNotFound(id.clone())),
+                })
+                .collect(),
+            Selection::HashTag(tag) => {
+                let tag = format!("#{}", tag.trim_start_matches('#'));
+                Ok(self
+                    .nodes
+                    .iter()
+                    .filter(|n| {
+                        let (hash_tags, _) = extract_hashtags(&n.label.0);
+                        hash_tags.iter().any(|t| t.to_string() == tag)
+                    })
+                    .map(|n| n.id.clone())
+                    .collect())
+            }
+            Selection::Reach(id, reach) => {
+                let mut selected: HashSet<Id> = self
+                    .find_reachable(id, *reach)?
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect();
+                selected.insert(id.clone());
+                Ok(selected)
+            }
         }
+    }
 
-        let (id, _) = self.insert_node(label.clone());
+    /// a new graph holding just the selected nodes and the edges between them, leaving this one
+    /// as it is. Ids are kept, and so are the high water marks, so nothing added to the slice
+    /// can clash with what's in this graph.
+    pub fn extract(&self, selection: &Selection) -> Result<Graph, GraphError> {
+        let selected = self.select(selection)?;
+
+        Ok(Graph {
+            node_high_water: self.node_high_water,
+            edge_high_water: self.edge_high_water,
+            nodes: self
+                .nodes
+                .iter()
+                .filter(|n| selected.contains(&n.id))
+                .cloned()
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .filter(|e| selected.contains(&e.from) && selected.contains(&e.to))
+                .cloned()
+                .collect(),
+            is_left_right: self.is_left_right,
+            is_strict_dag: self.is_strict_dag,
+            palette: self.palette.clone(),
+            show_legend: self.show_legend,
+            ..Graph::default()
+        })
+    }
 
-        self.link_edge(&id, to);
-        self.current_node = Some(id.clone());
+    /// the role of each node, in the same order as `nodes`.
+    fn node_roles(&self) -> Vec<NodeRole> {
+        let has_incoming: HashSet<&Id> = self.edges.iter().map(|e| &e.to).collect();
+        let has_outgoing: HashSet<&Id> = self.edges.iter().map(|e| &e.from).collect();
 
-        CommandResult::new(format!("inserted node {}: '{}' before {}", id, label, to))
+        self.nodes
+            .iter()
+            .map(
+                |n| match (has_incoming.contains(&n.id), has_outgoing.contains(&n.id)) {
+                    (false, true) => NodeRole::Root,
+                    (true, true) => NodeRole::Intermediate,
+                    (true, false) => NodeRole::Leaf,
+                    (false, false) => NodeRole::Isolated,
+                },
+            )
+            .collect()
     }
 
-    pub fn expand_edge(&mut self, edge_id: &Id, label: &Label) -> CommandResult {
-        let (from, to) = match self.find_edge_idx(edge_id) {
-            Some(idx) => {
-                let edge = &self.edges[idx];
-                (edge.from.clone(), edge.to.clone())
-            }
-            None => return CommandResult::new(format!("edge {} not found", edge_id)),
-        };
+    fn find_nodes_with_role(&self, matches: impl Fn(NodeRole) -> bool) -> Vec<Id> {
+        self.nodes
+            .iter()
+            .zip(self.node_roles())
+            .filter(|(_, role)| matches(*role))
+            .map(|(n, _)| n.id.clone())
+            .collect()
+    }
 
-        self.unlink_edge(edge_id);
-        let (new_id, _) = self.insert_node(label.clone());
-        self.link_edge(&from, &new_id);
-        self.link_edge(&new_id, &to);
-        self.current_node = Some(new_id.clone());
-        CommandResult::new(format!(
-            "injected {}: '{}' between {} and {}",
-            new_id, label, from, to
+    /// nodes which nothing links to; unlinked nodes count as both roots and leaves.
+    pub fn find_roots(&self) -> Vec<Id> {
+        self.find_nodes_with_role(|role| matches!(role, NodeRole::Root | NodeRole::Isolated))
+    }
+
+    /// nodes which don't link to anything.
+    pub fn find_leaves(&self) -> Vec<Id> {
+        self.find_nodes_with_role(|role| matches!(role, NodeRole::Leaf | NodeRole::Isolated))
+    }
+
+    pub fn set_direction(&mut self, is_left_right: bool) -> CommandOutcome {
+        self.is_left_right = is_left_right;
+        CommandOutcome::new(format!(
+            "Direction changed to {}",
+            if is_left_right { "LR" } else { "TB" }
         ))
     }
 
-    pub fn link_edge(&mut self, from: &Id, to: &Id) -> CommandResult {
-        if self.find_node_idx(from).is_none() {
-            return CommandResult::new(format!("source node {} not found", from));
+    pub fn palette(&self) -> Option<&str> {
+        self.palette.as_deref()
+    }
+
+    pub fn set_palette<S: Into<String>>(&mut self, name: S) -> CommandOutcome {
+        let name = name.into();
+        let msg = format!("hashtags now coloured from the {} palette", name);
+        self.palette = Some(name);
+        CommandOutcome::new(msg)
+    }
+
+    pub fn show_legend(&self) -> bool {
+        self.show_legend
+    }
+
+    pub fn set_show_legend(&mut self, show_legend: bool) -> CommandOutcome {
+        self.show_legend = show_legend;
+        if show_legend {
+            CommandOutcome::new("legend on")
+        } else {
+            CommandOutcome::new("legend off")
         }
+    }
 
-        if self.find_node_idx(to).is_none() {
-            return CommandResult::new(format!("target node {} not found", to));
+    /// every hashtag used in a label, in order, with the number of nodes which use it. The
+    /// filter doesn't apply; it's a key to the whole graph.
+    pub fn hashtag_counts(&self) -> Vec<(HashTag, usize)> {
+        let mut counts: BTreeMap<String, (HashTag, usize)> = BTreeMap::new();
+        for node in &self.nodes {
+            let (hash_tags, _) = extract_hashtags(&node.label.0);
+            for tag in hash_tags {
+                counts.entry(tag.to_string()).or_insert((tag, 0)).1 += 1;
+            }
         }
+        counts.into_values().collect()
+    }
 
-        // we know both exist; create the edge
-        let id = self.next_edge_id();
+    pub fn is_strict_dag(&self) -> bool {
+        self.is_strict_dag
+    }
 
-        let edge = Edge {
-            id: id.clone(),
-            from: from.clone(),
-            to: to.clone(),
-        };
+    pub fn set_strict_dag(&mut self, is_strict_dag: bool) -> CommandOutcome {
+        self.is_strict_dag = is_strict_dag;
 
-        self.edges.push(edge);
+        if !is_strict_dag {
+            return CommandOutcome::new("strict DAG mode off");
+        }
 
-        CommandResult::new(format!("Added edge {} from {} to {}", id, from, to))
+        let cycles = self.find_cycles();
+        match (cycles.cycles.len(), cycles.is_complete) {
+            (0, true) => CommandOutcome::new("strict DAG mode on"),
+            (n, true) => CommandOutcome::new(format!(
+                "strict DAG mode on, but the graph already has {} cycle(s); see 'cycles'",
+                n
+            )),
+            (n, false) => CommandOutcome::new(format!(
+                "strict DAG mode on, but the graph already has at least {} cycle(s); see 'cycles'",
+                n
+            )),
+        }
     }
 
-    fn delete_node(&mut self, id: &Id) -> CommandResult {
-        match self.find_node_idx(id) {
-            Some(idx) => {
-                // delete all edges to or from this node
-                let mut edges_touching: Vec<Id> = vec![];
+    /// every elementary cycle, as the ids of the nodes around it, starting from the earliest
+    /// node. There can be exponentially many, so it stops after `MAX_CYCLES` cycles, or after
+    /// `MAX_CYCLE_SEARCH_STEPS` steps of searching, whichever comes first.
+    pub fn find_cycles(&self) -> Cycles {
+        let successors: Vec<Vec<usize>> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut next: Vec<usize> = self
+                    .edges
+                    .iter()
+                    .filter(|e| e.from == node.id)
+                    .filter_map(|e| self.find_node_idx(&e.to))
+                    .collect();
+                // parallel edges go round the same cycle
+                next.sort_unstable();
+                next.dedup();
+                next
+            })
+            .collect();
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; self.nodes.len()];
+        for (from, next) in successors.iter().enumerate() {
+            for &to in next {
+                predecessors[to].push(from);
+            }
+        }
 
-                for edge in &self.edges {
-                    if (&edge.from == id || &edge.to == id) && !edges_touching.contains(&edge.id) {
-                        edges_touching.push(edge.id.clone())
+        let mut cycles = vec![];
+        let mut steps = 0;
+
+        // each cycle is found once, from its earliest node, by only visiting later nodes.
+        for start in 0..self.nodes.len() {
+            // only nodes which can get back to the start are worth a visit; without this, a
+            // dense part of the graph with no cycles through it could take forever.
+            let mut returns = vec![false; self.nodes.len()];
+            let mut queue = vec![start];
+            while let Some(n) = queue.pop() {
+                for &p in &predecessors[n] {
+                    if p > start && !returns[p] {
+                        returns[p] = true;
+                        queue.push(p);
                     }
                 }
+            }
+
+            let mut on_path = vec![false; self.nodes.len()];
+            let mut path = vec![start];
+            let mut stack = vec![successors[start].iter()];
+
+            while let Some(next) = stack.last_mut() {
+                steps += 1;
+                if steps > MAX_CYCLE_SEARCH_STEPS {
+                    return Cycles {
+                        cycles,
+                        is_complete: false,
+                    };
+                }
 
-                for delete in &edges_touching {
-                    if let Some(idx) = self.find_edge_idx(delete) {
-                        self.edges.remove(idx);
+                match next.next() {
+                    Some(&n) if n == start => {
+                        cycles.push(path.iter().map(|&i| self.nodes[i].id.clone()).collect());
+                        if cycles.len() >= MAX_CYCLES {
+                            return Cycles {
+                                cycles,
+                                is_complete: false,
+                            };
+                        }
+                    }
+                    Some(&n) if returns[n] && !on_path[n] => {
+                        on_path[n] = true;
+                        path.push(n);
+                        stack.push(successors[n].iter());
+                    }
+                    Some(_) => {}
+                    None => {
+                        stack.pop();
+                        if let Some(n) = path.pop() {
+                            on_path[n] = false;
+                        }
                     }
                 }
+            }
+        }
 
-                self.nodes.remove(idx);
+        Cycles {
+            cycles,
+            is_complete: true,
+        }
+    }
 
-                if self.current_node == Some(id.clone()) {
-                    self.current_node = None;
+    /// the edges along a shortest path from `from` to `to`, following edges forwards, if there
+    /// is one.
+    fn find_shortest_path(&self, from: &Id, to: &Id) -> Option<Vec<Id>> {
+        let mut came_by: HashMap<&Id, &Edge> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                let mut path = vec![];
+                let mut step = current;
+                while let Some(&edge) = came_by.get(step) {
+                    path.push(edge.id.clone());
+                    step = &edge.from;
                 }
+                path.reverse();
+                return Some(path);
+            }
 
-                CommandResult::new(format!("node {} removed", id))
+            for edge in self.outgoing(current) {
+                if &edge.to != from && !came_by.contains_key(&edge.to) {
+                    came_by.insert(&edge.to, edge);
+                    queue.push_back(&edge.to);
+                }
             }
-            None => CommandResult::new(format!("node {} not found", id)),
         }
-    }
+
+        None
+    }
+
+    /// in strict DAG mode, the reason an edge from `from` to `to` isn't allowed.
+    fn check_strict_dag(&self, from: &Id, to: &Id) -> Result<(), GraphError> {
+        if !self.is_strict_dag {
+            return Ok(());
+        }
+
+        if from == to {
+            return Err(GraphError::SelfLoop(from.clone()));
+        }
+
+        if let Some(existing) = self.edges.iter().find(|e| &e.from == from && &e.to == to) {
+            return Err(GraphError::DuplicateEdge {
+                from: from.clone(),
+                to: to.clone(),
+                existing: existing.id.clone(),
+            });
+        }
+
+        if let Some(path) = self.find_shortest_path(to, from) {
+            let mut cycle = vec![from.clone()];
+            cycle.extend(self.nodes_along(to, &path));
+            return Err(GraphError::WouldCreateCycle { cycle });
+        }
+
+        Ok(())
+    }
+
+    fn unlink_edge(&mut self, id: &Id) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_edge_idx(id)
+            .ok_or_else(|| GraphError::EdgeNotFound(id.clone()))?;
+        self.edges.remove(idx);
+
+        Ok(CommandOutcome {
+            removed_edges: vec![id.clone()],
+            ..CommandOutcome::new(format!("edge {} removed", id))
+        })
+    }
+
+    fn label_edge(&mut self, id: &Id, label: Option<Label>) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_edge_idx(id)
+            .ok_or_else(|| GraphError::EdgeNotFound(id.clone()))?;
+
+        let msg = match &label {
+            Some(label) => format!("edge {} labelled '{}'", id, label),
+            None => format!("edge {} label cleared", id),
+        };
+        self.edges[idx].label = label;
+
+        Ok(CommandOutcome {
+            changed_edges: vec![id.clone()],
+            ..CommandOutcome::new(msg)
+        })
+    }
+
+    /// point an existing edge at different nodes, keeping its id and label.
+    fn retarget_edge(&mut self, id: &Id, from: &Id, to: &Id) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_edge_idx(id)
+            .ok_or_else(|| GraphError::EdgeNotFound(id.clone()))?;
+
+        if self.find_node_idx(from).is_none() {
+            return Err(GraphError::SourceNodeNotFound(from.clone()));
+        }
+
+        if self.find_node_idx(to).is_none() {
+            return Err(GraphError::TargetNodeNotFound(to.clone()));
+        }
+
+        // the edge shouldn't count as a duplicate of itself, or close a cycle through its old route
+        let mut edge = self.edges.remove(idx);
+        let allowed = self.check_strict_dag(from, to);
+        if allowed.is_ok() {
+            edge.from = from.clone();
+            edge.to = to.clone();
+        }
+        self.edges.insert(idx, edge);
+        allowed?;
+
+        Ok(CommandOutcome {
+            changed_edges: vec![id.clone()],
+            ..CommandOutcome::new(format!("edge {} now links {} to {}", id, from, to))
+        })
+    }
+
+    fn reverse_edge(&mut self, id: &Id) -> Result<CommandOutcome, GraphError> {
+        let edge = &self.edges[self
+            .find_edge_idx(id)
+            .ok_or_else(|| GraphError::EdgeNotFound(id.clone()))?];
+        let (from, to) = (edge.to.clone(), edge.from.clone());
+
+        self.retarget_edge(id, &from, &to)
+    }
+
+    fn rename_node(&mut self, id: &Id, label: Label) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_node_idx(id)
+            .ok_or_else(|| GraphError::NodeNotFound(id.clone()))?;
+
+        self.current_node = Some(id.clone());
+        self.nodes[idx].label = label.clone();
+
+        Ok(CommandOutcome {
+            changed_nodes: vec![id.clone()],
+            ..CommandOutcome::new(format!("Node {} renamed to '{}'", id, label))
+        })
+    }
+
+    pub fn insert_node(&mut self, label: Label) -> (Id, CommandOutcome) {
+        let id = self.next_node_id();
+
+        let node = Node {
+            id: id.clone(),
+            label: label.clone(),
+        };
+
+        self.nodes.push(node);
+        self.current_node = Some(id.clone());
+
+        let outcome = CommandOutcome {
+            created_nodes: vec![id.clone()],
+            ..CommandOutcome::new(format!("inserted node {}: '{}'", id, label))
+        };
+        (id, outcome)
+    }
+
+    /// insert a node which already has an id, as when loading a saved graph.
+    pub fn insert_node_with_id(
+        &mut self,
+        id: Id,
+        label: Label,
+    ) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(&id).is_some() {
+            return Err(GraphError::DuplicateNodeId(id));
+        }
+
+        if let Some(n) = id_number(&id, 'n') {
+            self.raise_high_water_marks(n + 1, 0);
+        }
+
+        let msg = format!("inserted node {}: '{}'", id, label);
+        self.nodes.push(Node {
+            id: id.clone(),
+            label,
+        });
+        Ok(CommandOutcome {
+            created_nodes: vec![id],
+            ..CommandOutcome::new(msg)
+        })
+    }
+
+    pub fn select_node(&mut self, id: &Id) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(id).is_none() {
+            return Err(GraphError::NodeNotFound(id.clone()));
+        }
+
+        self.current_node = Some(id.clone());
+        Ok(CommandOutcome::new(format!("node {} selected", id)))
+    }
+
+    pub fn inject_after_node(
+        &mut self,
+        from: &Id,
+        label: &Label,
+    ) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(from).is_none() {
+            return Err(GraphError::SourceNodeNotFound(from.clone()));
+        }
+
+        let (id, _) = self.insert_node(label.clone());
+        let edge = self.link_edge(from, &id, None)?;
+        self.current_node = Some(id.clone());
+
+        Ok(CommandOutcome {
+            created_nodes: vec![id.clone()],
+            created_edges: edge.created_edges,
+            ..CommandOutcome::new(format!("inserted node {}: '{}' after {}", id, label, from))
+        })
+    }
+
+    pub fn inject_before_node(
+        &mut self,
+        to: &Id,
+        label: &Label,
+    ) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(to).is_none() {
+            return Err(GraphError::TargetNodeNotFound(to.clone()));
+        }
+
+        let (id, _) = self.insert_node(label.clone());
+        let edge = self.link_edge(&id, to, None)?;
+        self.current_node = Some(id.clone());
+
+        Ok(CommandOutcome {
+            created_nodes: vec![id.clone()],
+            created_edges: edge.created_edges,
+            ..CommandOutcome::new(format!("inserted node {}: '{}' before {}", id, label, to))
+        })
+    }
+
+    pub fn expand_edge(
+        &mut self,
+        edge_id: &Id,
+        label: &Label,
+    ) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_edge_idx(edge_id)
+            .ok_or_else(|| GraphError::EdgeNotFound(edge_id.clone()))?;
+        let edge = &self.edges[idx];
+        let (from, to, edge_label) = (edge.from.clone(), edge.to.clone(), edge.label.clone());
+
+        // any label stays with the edge leaving the original source
+        self.unlink_edge(edge_id)?;
+        let (new_id, _) = self.insert_node(label.clone());
+        let before = self.link_edge(&from, &new_id, edge_label)?;
+        let after = self.link_edge(&new_id, &to, None)?;
+        self.current_node = Some(new_id.clone());
+
+        Ok(CommandOutcome {
+            created_nodes: vec![new_id.clone()],
+            removed_edges: vec![edge_id.clone()],
+            created_edges: [before.created_edges, after.created_edges].concat(),
+            ..CommandOutcome::new(format!(
+                "injected {}: '{}' between {} and {}",
+                new_id, label, from, to
+            ))
+        })
+    }
+
+    pub fn link_edge(
+        &mut self,
+        from: &Id,
+        to: &Id,
+        label: Option<Label>,
+    ) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(from).is_none() {
+            return Err(GraphError::SourceNodeNotFound(from.clone()));
+        }
+
+        if self.find_node_idx(to).is_none() {
+            return Err(GraphError::TargetNodeNotFound(to.clone()));
+        }
+
+        self.check_strict_dag(from, to)?;
+
+        // we know both exist; create the edge
+        let id = self.next_edge_id();
+
+        let msg = edge_added_message(&id, from, to, &label);
+
+        let edge = Edge {
+            id: id.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            label,
+        };
+
+        self.edges.push(edge);
+
+        Ok(CommandOutcome {
+            created_edges: vec![id],
+            ..CommandOutcome::new(msg)
+        })
+    }
+
+    /// link two nodes with an edge which already has an id, as when loading a saved graph.
+    pub fn link_edge_with_id(
+        &mut self,
+        id: Id,
+        from: &Id,
+        to: &Id,
+        label: Option<Label>,
+    ) -> Result<CommandOutcome, GraphError> {
+        if self.find_edge_idx(&id).is_some() {
+            return Err(GraphError::DuplicateEdgeId(id));
+        }
+
+        if self.find_node_idx(from).is_none() {
+            return Err(GraphError::SourceNodeNotFound(from.clone()));
+        }
+
+        if self.find_node_idx(to).is_none() {
+            return Err(GraphError::TargetNodeNotFound(to.clone()));
+        }
+
+        if let Some(n) = id_number(&id, 'e') {
+            self.raise_high_water_marks(0, n + 1);
+        }
+
+        let msg = edge_added_message(&id, from, to, &label);
+        self.edges.push(Edge {
+            id: id.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            label,
+        });
+        Ok(CommandOutcome {
+            created_edges: vec![id],
+            ..CommandOutcome::new(msg)
+        })
+    }
+
+    fn delete_node(&mut self, id: &Id) -> Result<CommandOutcome, GraphError> {
+        let idx = self
+            .find_node_idx(id)
+            .ok_or_else(|| GraphError::NodeNotFound(id.clone()))?;
+
+        // delete all edges to or from this node
+        let mut edges_touching: Vec<Id> = vec![];
+
+        for edge in &self.edges {
+            if (&edge.from == id || &edge.to == id) && !edges_touching.contains(&edge.id) {
+                edges_touching.push(edge.id.clone())
+            }
+        }
+
+        for delete in &edges_touching {
+            if let Some(idx) = self.find_edge_idx(delete) {
+                self.edges.remove(idx);
+            }
+        }
+
+        self.nodes.remove(idx);
+
+        if self.current_node == Some(id.clone()) {
+            self.current_node = None;
+        }
+
+        Ok(CommandOutcome {
+            removed_nodes: vec![id.clone()],
+            removed_edges: edges_touching,
+            ..CommandOutcome::new(format!("node {} removed", id))
+        })
+    }
+
+    /// fold `from` into `into`, moving every edge touching `from` over to `into`. Edges which
+    /// would then loop back to `into`, or duplicate an edge it already has, are dropped; a
+    /// dropped edge's label moves to the edge it duplicates, if that has none.
+    fn merge_nodes(
+        &mut self,
+        from: &Id,
+        into: &Id,
+        join_labels: bool,
+    ) -> Result<CommandOutcome, GraphError> {
+        let from_idx = self
+            .find_node_idx(from)
+            .ok_or_else(|| GraphError::NodeNotFound(from.clone()))?;
+        let into_idx = self
+            .find_node_idx(into)
+            .ok_or_else(|| GraphError::NodeNotFound(into.clone()))?;
+        if from == into {
+            return Err(GraphError::MergeIntoItself(from.clone()));
+        }
+
+        let before = self.snapshot();
+
+        if join_labels {
+            let (into_tags, into_text) = extract_hashtags(&self.nodes[into_idx].label.0);
+            let (from_tags, from_text) = extract_hashtags(&self.nodes[from_idx].label.0);
+            let mut tags: Vec<_> = into_tags
+                .iter()
+                .chain(&from_tags)
+                .map(|t| t.to_string())
+                .collect();
+            tags.sort();
+            tags.dedup();
+
+            let mut label = vec![into_text, from_text];
+            label.extend(tags);
+            label.retain(|part| !part.is_empty());
+            self.nodes[into_idx].label = Label::new(label.join(" "));
+        }
+
+        // move every edge over first, so duplicates are spotted wherever they are in the list
+        let mut moved = vec![];
+        for edge in &mut self.edges {
+            let is_moving = &edge.from == from || &edge.to == from;
+            if &edge.from == from {
+                edge.from = into.clone();
+            }
+            if &edge.to == from {
+                edge.to = into.clone();
+            }
+            if is_moving {
+                moved.push(edge.id.clone());
+            }
+        }
+
+        let mut removed_edges: Vec<Id> = vec![];
+        for id in &moved {
+            let edge = &self.edges[self.find_edge_idx(id).expect("edge was just moved")];
+            let (edge_from, edge_to, label) =
+                (edge.from.clone(), edge.to.clone(), edge.label.clone());
+            let duplicate = self.edges.iter().position(|e| {
+                &e.id != id
+                    && e.from == edge_from
+                    && e.to == edge_to
+                    && !removed_edges.contains(&e.id)
+            });
+
+            if edge_from == edge_to {
+                removed_edges.push(id.clone());
+            } else if let Some(duplicate) = duplicate {
+                let duplicate = &mut self.edges[duplicate];
+                if duplicate.label.is_none() {
+                    duplicate.label = label;
+                }
+                removed_edges.push(id.clone());
+            }
+        }
+        let changed_edges = moved
+            .into_iter()
+            .filter(|id| !removed_edges.contains(id))
+            .collect();
+
+        self.edges.retain(|e| !removed_edges.contains(&e.id));
+        self.nodes.remove(from_idx);
+
+        if self.is_strict_dag {
+            let cycle = self
+                .outgoing(into)
+                .find_map(|e| self.find_shortest_path(&e.to, into).map(|path| (e, path)))
+                .map(|(e, path)| {
+                    let mut cycle = vec![into.clone()];
+                    cycle.extend(self.nodes_along(&e.to, &path));
+                    cycle
+                });
+            if let Some(cycle) = cycle {
+                self.restore(before);
+                return Err(GraphError::WouldCreateCycle { cycle });
+            }
+        }
+
+        // anything pointing at the old node now points at the one it became
+        let renamed = |id: &mut Id| {
+            if id == from {
+                *id = into.clone();
+            }
+        };
+        if let Some(current_node) = &mut self.current_node {
+            renamed(current_node);
+        }
+        match &mut self.current_query {
+            Some(Query::Reach(id, _)) => renamed(id),
+            Some(Query::Paths {
+                from: path_from,
+                to: path_to,
+                ..
+            }) => {
+                renamed(path_from);
+                renamed(path_to);
+            }
+            Some(Query::Search(_)) | None => {}
+        }
+
+        Ok(CommandOutcome {
+            removed_nodes: vec![from.clone()],
+            changed_nodes: vec![into.clone()],
+            changed_edges,
+            removed_edges: removed_edges.clone(),
+            ..CommandOutcome::new(format!(
+                "merged {} into {}, dropping {} edges",
+                from,
+                into,
+                removed_edges.len()
+            ))
+        })
+    }
+
+    /// remove a node, linking each node which led to it to each node it led to; the reverse of
+    /// `expand_edge`, so the label on an edge into the node moves to the edges replacing it.
+    fn collapse_node(&mut self, id: &Id) -> Result<CommandOutcome, GraphError> {
+        if self.find_node_idx(id).is_none() {
+            return Err(GraphError::NodeNotFound(id.clone()));
+        }
+
+        // a loop through the node would only bridge to itself
+        let incoming: Vec<(Id, Option<Label>)> = self
+            .edges
+            .iter()
+            .filter(|e| &e.to == id && &e.from != id)
+            .map(|e| (e.from.clone(), e.label.clone()))
+            .collect();
+        let successors: Vec<Id> = self
+            .outgoing(id)
+            .filter(|e| &e.to != id)
+            .map(|e| e.to.clone())
+            .collect();
+
+        let before = self.snapshot();
+        let current_node = self.current_node.clone();
+        let mut outcome = self.delete_node(id)?;
+
+        let mut lines = vec![];
+        for (from, label) in &incoming {
+            for to in &successors {
+                let is_already_linked = from == to || self.outgoing(from).any(|e| &e.to == to);
+                if is_already_linked {
+                    continue;
+                }
+
+                // in strict DAG mode, a graph which already had a cycle may refuse a bridge
+                let linked = match self.link_edge(from, to, label.clone()) {
+                    Ok(linked) => linked,
+                    Err(e) => {
+                        self.restore(before);
+                        self.current_node = current_node;
+                        return Err(e);
+                    }
+                };
+                lines.push(linked.message);
+                outcome.created_edges.extend(linked.created_edges);
+            }
+        }
+
+        outcome.message = if lines.is_empty() {
+            format!("node {} collapsed, with nothing to link", id)
+        } else {
+            format!("node {} collapsed\n{}", id, lines.join("\n"))
+        };
+        Ok(outcome)
+    }
+}
+
+fn edge_added_message(id: &Id, from: &Id, to: &Id, label: &Option<Label>) -> String {
+    match label {
+        Some(label) => format!(
+            "Added edge {} from {} to {} labelled '{}'",
+            id, from, to, label
+        ),
+        None => format!("Added edge {} from {} to {}", id, from, to),
+    }
+}
+
+/// how many cycles `find_cycles` will list before giving up.
+pub const MAX_CYCLES: usize = 100;
+
+/// how many steps `find_cycles` will take along edges looking for cycles before giving up.
+/// Each is cheap; it's there so a dense, tangled graph can't hang the repl.
+pub const MAX_CYCLE_SEARCH_STEPS: usize = 100_000;
+
+/// what `find_cycles` found, and whether it looked everywhere; when it had to stop early there
+/// may be more.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycles {
+    pub cycles: Vec<Vec<Id>>,
+    pub is_complete: bool,
+}
+
+/// how many paths `find_paths` will list before giving up.
+pub const MAX_PATHS: usize = 100;
+
+fn node_matches_search(n: &Node, sub_label: &Label) -> bool {
+    n.label.0.contains(&sub_label.0)
+}
+
+/// the nodes and edges which differ between two states of the graph, as when undoing.
+fn diff_snapshots<S: Into<String>>(
+    before: &Snapshot,
+    after: &Snapshot,
+    message: S,
+) -> CommandOutcome {
+    fn diff<T: PartialEq>(
+        before: &[T],
+        after: &[T],
+        id: impl Fn(&T) -> &Id,
+    ) -> (Vec<Id>, Vec<Id>, Vec<Id>) {
+        let mut created = vec![];
+        let mut removed = vec![];
+        let mut changed = vec![];
+        for item in after {
+            match before.iter().find(|b| id(b) == id(item)) {
+                None => created.push(id(item).clone()),
+                Some(b) if b != item => changed.push(id(item).clone()),
+                Some(_) => {}
+            }
+        }
+        for item in before {
+            if !after.iter().any(|a| id(a) == id(item)) {
+                removed.push(id(item).clone());
+            }
+        }
+        (created, removed, changed)
+    }
+
+    let (created_nodes, removed_nodes, changed_nodes) =
+        diff(&before.nodes, &after.nodes, |n: &Node| &n.id);
+    let (created_edges, removed_edges, changed_edges) =
+        diff(&before.edges, &after.edges, |e: &Edge| &e.id);
+
+    CommandOutcome {
+        message: message.into(),
+        created_nodes,
+        removed_nodes,
+        changed_nodes,
+        created_edges,
+        removed_edges,
+        changed_edges,
+    }
+}
+
+/// the number in a generated id like `n12` or `e4`, if it is one.
+fn id_number(id: &Id, prefix: char) -> Option<usize> {
+    id.0.strip_prefix(prefix)?.parse().ok()
 }
 
 #[cfg(test)]
@@ -320,7 +1772,927 @@ mod tests {
         let (id2, _) = graph.insert_node(Label::new("second node"));
 
         assert_eq!(graph.current_node, Some(id2));
-        graph.select_node(&id1);
+        graph.select_node(&id1).unwrap();
         assert_eq!(graph.current_node, Some(id1));
     }
+
+    fn insert(graph: &mut Graph, label: &str) {
+        graph
+            .apply_command(GraphCommand::InsertNode {
+                label: Label::new(label),
+            })
+            .unwrap();
+    }
+
+    fn link(graph: &mut Graph, from: &str, to: &str) {
+        graph
+            .apply_command(GraphCommand::LinkEdge {
+                from: Id::new(from),
+                to: Id::new(to),
+                label: None,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn undo_restores_deleted_node_and_its_edges() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        insert(&mut graph, "c");
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        let before = graph.snapshot();
+
+        graph
+            .apply_command(GraphCommand::DeleteNode { id: Id::new("n1") })
+            .unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.edges.len(), 0);
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.snapshot(), before);
+    }
+
+    #[test]
+    fn undo_reverts_expand_edge() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        link(&mut graph, "n0", "n1");
+        let before = graph.snapshot();
+
+        graph
+            .apply_command(GraphCommand::ExpandEdge {
+                id: Id::new("e0"),
+                label: Label::new("between"),
+            })
+            .unwrap();
+        assert_eq!(graph.nodes.len(), 3);
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.snapshot(), before);
+
+        // and the ids the undone command used are handed out again
+        insert(&mut graph, "c");
+        assert_eq!(graph.find_node_label(&Id::new("n2")), Some(Label::new("c")));
+    }
+
+    #[test]
+    fn selecting_and_turning_are_not_undone() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+
+        graph
+            .apply_command(GraphCommand::SelectNode { id: Id::new("n0") })
+            .unwrap();
+        graph
+            .apply_command(GraphCommand::SetDirection {
+                is_left_right: true,
+            })
+            .unwrap();
+
+        // undo skips straight past them to the last edit
+        let outcome = graph.apply_command(GraphCommand::Undo).unwrap();
+        assert!(outcome.removed_nodes.contains(&Id::new("n1")));
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!(graph.current_node, Some(Id::new("n0")));
+        assert!(graph.is_left_right);
+    }
+
+    #[test]
+    fn redo_reapplies_undone_command() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        graph
+            .apply_command(GraphCommand::RenameNode {
+                id: Id::new("n0"),
+                label: Label::new("renamed"),
+            })
+            .unwrap();
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.find_node_label(&Id::new("n0")), Some(Label::new("a")));
+
+        graph.apply_command(GraphCommand::Redo).unwrap();
+        assert_eq!(
+            graph.find_node_label(&Id::new("n0")),
+            Some(Label::new("renamed"))
+        );
+
+        let result = graph.apply_command(GraphCommand::Redo);
+        assert_eq!(result, Err(GraphError::NothingToRedo));
+    }
+
+    #[test]
+    fn outcomes_list_what_changed() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        link(&mut graph, "n0", "n1");
+
+        let outcome = graph
+            .apply_command(GraphCommand::ExpandEdge {
+                id: Id::new("e0"),
+                label: Label::new("between"),
+            })
+            .unwrap();
+        assert_eq!(outcome.created_nodes, vec![Id::new("n2")]);
+        assert_eq!(outcome.removed_edges, vec![Id::new("e0")]);
+        assert_eq!(outcome.created_edges, vec![Id::new("e1"), Id::new("e2")]);
+
+        let outcome = graph
+            .apply_command(GraphCommand::DeleteNode { id: Id::new("n2") })
+            .unwrap();
+        assert_eq!(outcome.removed_nodes, vec![Id::new("n2")]);
+        assert_eq!(outcome.removed_edges, vec![Id::new("e1"), Id::new("e2")]);
+
+        let outcome = graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(outcome.created_nodes, vec![Id::new("n2")]);
+        assert_eq!(outcome.created_edges, vec![Id::new("e1"), Id::new("e2")]);
+    }
+
+    #[test]
+    fn failed_commands_are_not_recorded() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        let result = graph.apply_command(GraphCommand::DeleteNode { id: Id::new("n9") });
+        assert_eq!(result, Err(GraphError::NodeNotFound(Id::new("n9"))));
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.nodes.len(), 0);
+    }
+
+    #[test]
+    fn replacing_the_graph_can_be_undone() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        let before = graph.snapshot();
+
+        let mut other = Graph::new();
+        insert(&mut other, "x");
+        insert(&mut other, "y");
+        graph.replace(other, "import other.dot");
+        assert_eq!(graph.nodes.len(), 2);
+
+        let outcome = graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(outcome.message, "undone: import other.dot");
+        assert_eq!(outcome.changed_nodes, vec![Id::new("n0")]);
+        assert_eq!(outcome.removed_nodes, vec![Id::new("n1")]);
+        assert_eq!(graph.snapshot(), before);
+    }
+
+    #[test]
+    fn inserting_with_ids_raises_high_water_marks() {
+        let mut graph = Graph::new();
+        graph
+            .insert_node_with_id(Id::new("n7"), Label::new("a"))
+            .unwrap();
+        graph
+            .insert_node_with_id(Id::new("n3"), Label::new("b"))
+            .unwrap();
+        graph
+            .insert_node_with_id(Id::new("custom"), Label::new("c"))
+            .unwrap();
+        graph
+            .link_edge_with_id(Id::new("e5"), &Id::new("n7"), &Id::new("n3"), None)
+            .unwrap();
+
+        assert_eq!(graph.node_high_water(), 8);
+        assert_eq!(graph.edge_high_water(), 6);
+
+        let (id, _) = graph.insert_node(Label::new("d"));
+        assert_eq!(id, Id::new("n8"));
+    }
+
+    #[test]
+    fn refuses_duplicate_ids() {
+        let mut graph = Graph::new();
+        graph
+            .insert_node_with_id(Id::new("n0"), Label::new("a"))
+            .unwrap();
+        let result = graph.insert_node_with_id(Id::new("n0"), Label::new("b"));
+        assert_eq!(result, Err(GraphError::DuplicateNodeId(Id::new("n0"))));
+        assert_eq!(graph.nodes.len(), 1);
+    }
+
+    #[test]
+    fn labels_edges() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        graph
+            .apply_command(GraphCommand::LinkEdge {
+                from: Id::new("n0"),
+                to: Id::new("n1"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+        assert_eq!(graph.edges[0].label, Some(Label::new("causes")));
+
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e0"),
+                label: Some(Label::new("blocks")),
+            })
+            .unwrap();
+        assert_eq!(graph.edges[0].label, Some(Label::new("blocks")));
+
+        graph
+            .apply_command(GraphCommand::ExpandEdge {
+                id: Id::new("e0"),
+                label: Label::new("between"),
+            })
+            .unwrap();
+        assert_eq!(graph.edges[0].label, Some(Label::new("blocks")));
+        assert_eq!(graph.edges[1].label, None);
+
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e1"),
+                label: None,
+            })
+            .unwrap();
+        assert_eq!(graph.edges[0].label, None);
+    }
+
+    #[test]
+    fn strict_dag_mode_refuses_cycles_loops_and_duplicates() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        insert(&mut graph, "c");
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        graph
+            .apply_command(GraphCommand::SetStrictDag {
+                is_strict_dag: true,
+            })
+            .unwrap();
+
+        let link_result = |graph: &mut Graph, from: &str, to: &str| {
+            graph.apply_command(GraphCommand::LinkEdge {
+                from: Id::new(from),
+                to: Id::new(to),
+                label: None,
+            })
+        };
+
+        assert_eq!(
+            link_result(&mut graph, "n1", "n1"),
+            Err(GraphError::SelfLoop(Id::new("n1")))
+        );
+        assert_eq!(
+            link_result(&mut graph, "n0", "n1"),
+            Err(GraphError::DuplicateEdge {
+                from: Id::new("n0"),
+                to: Id::new("n1"),
+                existing: Id::new("e0"),
+            })
+        );
+
+        let cycle = link_result(&mut graph, "n2", "n0").unwrap_err();
+        assert_eq!(
+            cycle.to_string(),
+            "can't make the cycle n2 -> n0 -> n1 -> n2 in strict DAG mode"
+        );
+
+        // a shortcut doesn't make a cycle
+        assert!(link_result(&mut graph, "n0", "n2").is_ok());
+
+        graph
+            .apply_command(GraphCommand::SetStrictDag {
+                is_strict_dag: false,
+            })
+            .unwrap();
+        assert!(link_result(&mut graph, "n2", "n0").is_ok());
+    }
+
+    #[test]
+    fn finds_every_cycle() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c", "d"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n0");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n2", "n0");
+        link(&mut graph, "n2", "n0");
+        link(&mut graph, "n3", "n3");
+
+        let ids = |ids: &[&str]| ids.iter().map(|id| Id::new(*id)).collect::<Vec<_>>();
+        assert_eq!(
+            graph.find_cycles(),
+            Cycles {
+                cycles: vec![ids(&["n0", "n1"]), ids(&["n0", "n1", "n2"]), ids(&["n3"])],
+                is_complete: true
+            }
+        );
+
+        let outcome = graph.set_strict_dag(true);
+        assert_eq!(
+            outcome.message,
+            "strict DAG mode on, but the graph already has 3 cycle(s); see 'cycles'"
+        );
+    }
+
+    #[test]
+    fn a_refused_collapse_changes_nothing() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n2", "n0");
+        graph.set_strict_dag(true);
+        graph
+            .apply_command(GraphCommand::SelectNode { id: Id::new("n1") })
+            .unwrap();
+        let before = graph.snapshot();
+        let history = graph.export_history();
+
+        // bridging n0 to n2 would close the cycle the other way round
+        let result = graph.apply_command(GraphCommand::CollapseNode { id: Id::new("n1") });
+        assert_eq!(
+            result,
+            Err(GraphError::WouldCreateCycle {
+                cycle: vec![Id::new("n0"), Id::new("n2"), Id::new("n0")]
+            })
+        );
+        assert_eq!(graph.snapshot(), before);
+        assert_eq!(graph.export_history(), history);
+        assert_eq!(graph.current_node, Some(Id::new("n1")));
+    }
+
+    #[test]
+    fn gives_up_looking_for_cycles_in_a_tangle() {
+        // every node links to every later one, and the last back to the first: there are 2^28
+        // cycles through the whole thing, and far more paths which go nowhere.
+        let mut graph = Graph::new();
+        for i in 0..30 {
+            insert(&mut graph, &format!("{}", i));
+        }
+        for from in 0..30 {
+            for to in from + 1..30 {
+                link(&mut graph, &format!("n{}", from), &format!("n{}", to));
+            }
+        }
+        link(&mut graph, "n29", "n0");
+
+        let cycles = graph.find_cycles();
+        assert!(!cycles.is_complete);
+        assert!(cycles.cycles.len() <= MAX_CYCLES);
+
+        // and the same tangle without the edge back has nothing to search
+        graph.unlink_edge(&Id::new("e435")).unwrap();
+        let cycles = graph.find_cycles();
+        assert_eq!(cycles.cycles, Vec::<Vec<Id>>::new());
+        assert!(cycles.is_complete);
+    }
+
+    #[test]
+    fn finds_everything_upstream_and_downstream_by_depth() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c", "d", "e"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n0", "n2");
+        link(&mut graph, "n2", "n0");
+        link(&mut graph, "n3", "n2");
+
+        let reached = |ids: &[(&str, usize)]| {
+            ids.iter()
+                .map(|(id, depth)| (Id::new(*id), *depth))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            graph.find_reachable(&Id::new("n0"), Reach::Downstream),
+            Ok(reached(&[("n1", 1), ("n2", 1)]))
+        );
+        assert_eq!(
+            graph.find_reachable(&Id::new("n2"), Reach::Upstream),
+            Ok(reached(&[("n1", 1), ("n0", 1), ("n3", 1)]))
+        );
+        assert_eq!(
+            graph.find_reachable(&Id::new("n3"), Reach::Downstream),
+            Ok(reached(&[("n2", 1), ("n0", 2), ("n1", 3)]))
+        );
+        assert_eq!(
+            graph.find_reachable(&Id::new("n4"), Reach::Upstream),
+            Ok(vec![])
+        );
+        assert_eq!(
+            graph.find_reachable(&Id::new("n9"), Reach::Upstream),
+            Err(GraphError::NodeNotFound(Id::new("n9")))
+        );
+    }
+
+    #[test]
+    fn finds_roots_and_leaves() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c", "d", "e"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n3", "n1");
+        link(&mut graph, "n2", "n2");
+
+        let ids = |ids: &[&str]| ids.iter().map(|id| Id::new(*id)).collect::<Vec<_>>();
+        assert_eq!(graph.find_roots(), ids(&["n0", "n3", "n4"]));
+        assert_eq!(graph.find_leaves(), ids(&["n4"]));
+
+        graph
+            .apply_command(GraphCommand::UnlinkEdge { id: Id::new("e3") })
+            .unwrap();
+        assert_eq!(graph.find_leaves(), ids(&["n2", "n4"]));
+    }
+
+    #[test]
+    fn finds_shortest_and_all_paths() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c", "d", "e"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n2", "n3");
+        link(&mut graph, "n0", "n2");
+        link(&mut graph, "n0", "n2");
+        link(&mut graph, "n3", "n0");
+
+        let paths = |paths: &[&[&str]]| {
+            paths
+                .iter()
+                .map(|path| path.iter().map(|id| Id::new(*id)).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let (n0, n3, n4) = (Id::new("n0"), Id::new("n3"), Id::new("n4"));
+
+        assert_eq!(
+            graph.find_paths(&n0, &n3, PathCount::Shortest),
+            Ok(paths(&[&["e3", "e2"]]))
+        );
+        assert_eq!(
+            graph.find_paths(&n0, &n3, PathCount::All),
+            Ok(paths(&[&["e3", "e2"], &["e4", "e2"], &["e0", "e1", "e2"]]))
+        );
+        assert_eq!(graph.find_paths(&n0, &n4, PathCount::All), Ok(vec![]));
+        assert_eq!(
+            graph.find_paths(&Id::new("n9"), &n4, PathCount::All),
+            Err(GraphError::SourceNodeNotFound(Id::new("n9")))
+        );
+
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e3"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+        assert_eq!(
+            graph
+                .highlight_paths(&n0, &n3, PathCount::Shortest)
+                .unwrap()
+                .to_string(),
+            "shortest path from n0 to n3,\na (n0) --causes--> c (n2) --> d (n3)\n"
+        );
+    }
+
+    #[test]
+    fn extracts_a_slice_without_changing_the_graph() {
+        let mut graph = Graph::new();
+        for label in ["a #sales", "b", "c #sales #ops", "d"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n0", "n2");
+        link(&mut graph, "n3", "n0");
+
+        let slice = graph.extract(&Selection::HashTag("sales".into())).unwrap();
+        assert_eq!(slice.node_count(), 2);
+        assert_eq!(slice.edge_count(), 1);
+        assert_eq!(
+            slice.find_node_label(&Id::new("n2")),
+            Some(Label::new("c #sales #ops"))
+        );
+        assert_eq!(slice.node_high_water(), 4);
+
+        let slice = graph
+            .extract(&Selection::Reach(Id::new("n1"), Reach::Upstream))
+            .unwrap();
+        assert_eq!(slice.node_count(), 3);
+        assert_eq!(slice.edge_count(), 2);
+
+        let slice = graph
+            .extract(&Selection::Nodes(vec![Id::new("n0"), Id::new("n3")]))
+            .unwrap();
+        assert_eq!(slice.node_count(), 2);
+        assert_eq!(slice.edge_count(), 1);
+
+        assert_eq!(
+            graph.extract(&Selection::Nodes(vec![Id::new("n9")])).err(),
+            Some(GraphError::NodeNotFound(Id::new("n9")))
+        );
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.edge_count(), 4);
+    }
+
+    #[test]
+    fn merges_another_graph_with_fresh_ids() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "late");
+        insert(&mut graph, "angry");
+        link(&mut graph, "n0", "n1");
+
+        let mut other = Graph::new();
+        insert(&mut other, "angry");
+        insert(&mut other, "leaves");
+        link(&mut other, "n0", "n1");
+
+        let (outcome, ids) = graph.merge(&other, false, "merge other").unwrap();
+        let pairs = |ids: &[(&str, &str)]| {
+            ids.iter()
+                .map(|(old, new)| (Id::new(*old), Id::new(*new)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids, pairs(&[("n0", "n2"), ("n1", "n3")]));
+        assert_eq!(outcome.created_nodes, vec![Id::new("n2"), Id::new("n3")]);
+        assert_eq!(outcome.created_edges, vec![Id::new("e1")]);
+        assert_eq!(
+            graph.find_node_label(&Id::new("n3")),
+            Some(Label::new("leaves"))
+        );
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.node_count(), 2);
+
+        let (outcome, ids) = graph.merge(&other, true, "merge other").unwrap();
+        assert_eq!(ids, pairs(&[("n0", "n1"), ("n1", "n2")]));
+        assert_eq!(
+            outcome.message,
+            "merged 2 nodes, 1 of them matched by label, and 1 edges"
+        );
+
+        // merging the same graph again adds nothing
+        let (outcome, _) = graph.merge(&other, true, "merge other").unwrap();
+        assert!(outcome.created_nodes.is_empty());
+        assert!(outcome.created_edges.is_empty());
+    }
+
+    #[test]
+    fn refused_edges_stop_the_whole_merge() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        link(&mut graph, "n0", "n1");
+        graph.set_strict_dag(true);
+
+        let mut other = Graph::new();
+        insert(&mut other, "b");
+        insert(&mut other, "a");
+        link(&mut other, "n0", "n1");
+
+        assert!(matches!(
+            graph.merge(&other, true, "merge other"),
+            Err(GraphError::WouldCreateCycle { .. })
+        ));
+        assert_eq!(graph.node_count(), 2);
+        assert_eq!(graph.edge_count(), 1);
+        assert_eq!(
+            graph.apply_command(GraphCommand::Undo).unwrap().message,
+            "undone: Link the <n0> node to the <n1> node"
+        );
+    }
+
+    #[test]
+    fn collapsing_a_node_bridges_its_edges() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "middle", "c", "d"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n2");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n2", "n3");
+        link(&mut graph, "n2", "n4");
+        link(&mut graph, "n0", "n3");
+        link(&mut graph, "n2", "n2");
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e1"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+
+        let outcome = graph
+            .apply_command(GraphCommand::CollapseNode { id: Id::new("n2") })
+            .unwrap();
+
+        assert_eq!(outcome.removed_nodes, vec![Id::new("n2")]);
+        assert_eq!(outcome.removed_edges.len(), 5);
+        assert_eq!(
+            outcome.created_edges,
+            vec![Id::new("e6"), Id::new("e7"), Id::new("e8")]
+        );
+        assert_eq!(
+            outcome.message,
+            "node n2 collapsed\n\
+             Added edge e6 from n0 to n4\n\
+             Added edge e7 from n1 to n3 labelled 'causes'\n\
+             Added edge e8 from n1 to n4 labelled 'causes'"
+        );
+        assert_eq!(graph.edge_count(), 4);
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.node_count(), 5);
+        assert_eq!(graph.edge_count(), 6);
+    }
+
+    #[test]
+    fn merging_nodes_moves_their_edges() {
+        let mut graph = Graph::new();
+        for label in ["a", "late #ops", "b", "delayed #sales", "c"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n3");
+        link(&mut graph, "n3", "n2");
+        link(&mut graph, "n1", "n3");
+        link(&mut graph, "n3", "n4");
+        link(&mut graph, "n1", "n4");
+        link(&mut graph, "n0", "n1");
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e3"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+        graph
+            .highlight_reachable(&Id::new("n3"), Reach::Downstream)
+            .unwrap();
+
+        let outcome = graph
+            .apply_command(GraphCommand::MergeNodes {
+                from: Id::new("n3"),
+                into: Id::new("n1"),
+                join_labels: true,
+            })
+            .unwrap();
+
+        // n0 -> n3 duplicates n0 -> n1, n1 -> n3 becomes a loop, n3 -> n4 duplicates n1 -> n4
+        let ids = |ids: &[&str]| ids.iter().map(|id| Id::new(*id)).collect::<Vec<_>>();
+        assert_eq!(outcome.removed_edges, ids(&["e0", "e2", "e3"]));
+        assert_eq!(outcome.changed_edges, ids(&["e1"]));
+        assert_eq!(outcome.message, "merged n3 into n1, dropping 3 edges");
+        assert_eq!(
+            graph.find_node_label(&Id::new("n1")),
+            Some(Label::new("late delayed #ops #sales"))
+        );
+        assert_eq!(graph.find_roots(), ids(&["n0"]));
+        assert_eq!(graph.find_leaves(), ids(&["n2", "n4"]));
+        assert_eq!(
+            graph.find_paths(&Id::new("n1"), &Id::new("n4"), PathCount::All),
+            Ok(vec![ids(&["e4"])])
+        );
+
+        // the dropped edge's label moved to the one it duplicated
+        assert_eq!(
+            graph
+                .highlight_paths(&Id::new("n1"), &Id::new("n4"), PathCount::Shortest)
+                .unwrap()
+                .to_string(),
+            "shortest path from n1 to n4,\nlate delayed #ops #sales (n1) --causes--> c (n4)\n"
+        );
+
+        assert_eq!(
+            graph.apply_command(GraphCommand::MergeNodes {
+                from: Id::new("n1"),
+                into: Id::new("n1"),
+                join_labels: false,
+            }),
+            Err(GraphError::MergeIntoItself(Id::new("n1")))
+        );
+    }
+
+    #[test]
+    fn merging_nodes_cant_make_a_cycle_in_strict_dag_mode() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        graph.set_strict_dag(true);
+
+        let merge = GraphCommand::MergeNodes {
+            from: Id::new("n2"),
+            into: Id::new("n0"),
+            join_labels: false,
+        };
+        assert!(matches!(
+            graph.apply_command(merge),
+            Err(GraphError::WouldCreateCycle { .. })
+        ));
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[test]
+    fn reversing_and_retargeting_keep_the_edge_id() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e0"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+
+        let outcome = graph
+            .apply_command(GraphCommand::ReverseEdge { id: Id::new("e0") })
+            .unwrap();
+        assert_eq!(outcome.message, "edge e0 now links n1 to n0");
+        assert_eq!(outcome.changed_edges, vec![Id::new("e0")]);
+        assert_eq!(
+            (&graph.edges[0].from, &graph.edges[0].to),
+            (&Id::new("n1"), &Id::new("n0"))
+        );
+
+        graph
+            .apply_command(GraphCommand::RetargetEdge {
+                id: Id::new("e0"),
+                from: Id::new("n1"),
+                to: Id::new("n2"),
+            })
+            .unwrap();
+        assert_eq!(graph.edges[0].id, Id::new("e0"));
+        assert_eq!(graph.edges[0].to, Id::new("n2"));
+        assert_eq!(graph.edges[0].label, Some(Label::new("causes")));
+
+        assert_eq!(
+            graph.apply_command(GraphCommand::RetargetEdge {
+                id: Id::new("e0"),
+                from: Id::new("n1"),
+                to: Id::new("n9"),
+            }),
+            Err(GraphError::TargetNodeNotFound(Id::new("n9")))
+        );
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.edges[0].to, Id::new("n0"));
+    }
+
+    #[test]
+    fn strict_dag_mode_checks_moved_edges() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c"] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n0", "n1");
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n0", "n2");
+        graph.set_strict_dag(true);
+
+        assert_eq!(
+            graph.apply_command(GraphCommand::RetargetEdge {
+                id: Id::new("e2"),
+                from: Id::new("n2"),
+                to: Id::new("n0"),
+            }),
+            Err(GraphError::WouldCreateCycle {
+                cycle: vec![Id::new("n2"), Id::new("n0"), Id::new("n1"), Id::new("n2")]
+            })
+        );
+        assert_eq!(graph.edges[2].from, Id::new("n0"));
+
+        // turning an edge around doesn't make it a duplicate of itself
+        graph
+            .apply_command(GraphCommand::ReverseEdge { id: Id::new("e1") })
+            .unwrap();
+        assert_eq!(graph.edges[1].from, Id::new("n2"));
+    }
+
+    #[test]
+    fn filters_nodes_by_their_tags() {
+        let mut graph = Graph::new();
+        for label in [
+            "sets out #Frodo",
+            "meets #Frodo #Gandalf",
+            "smokes #Gandalf",
+            "eats",
+        ] {
+            insert(&mut graph, label);
+        }
+        link(&mut graph, "n1", "n2");
+        link(&mut graph, "n2", "n3");
+
+        let frodo = || Box::new(TagExpr::Tag("Frodo".into()));
+        let gandalf = || Box::new(TagExpr::Tag("Gandalf".into()));
+        let filter = Filter {
+            expr: TagExpr::And(frodo(), Box::new(TagExpr::Not(gandalf()))),
+            with_neighbours: false,
+        };
+        assert_eq!(
+            graph.set_filter(Some(filter)).to_string(),
+            "showing 1 of 4 nodes, for #Frodo and not #Gandalf"
+        );
+        assert_eq!(graph.filtered_nodes(), Some(HashSet::from([Id::new("n0")])));
+
+        let filter = Filter {
+            expr: TagExpr::Not(Box::new(TagExpr::Or(frodo(), gandalf()))),
+            with_neighbours: true,
+        };
+        assert_eq!(
+            filter.to_string(),
+            "not (#Frodo or #Gandalf) with neighbours"
+        );
+        graph.set_filter(Some(filter));
+        assert_eq!(
+            graph.filtered_nodes(),
+            Some(HashSet::from([Id::new("n2"), Id::new("n3")]))
+        );
+
+        assert_eq!(
+            graph.set_filter(None).to_string(),
+            "filter off, showing all 4 nodes"
+        );
+        assert_eq!(graph.filtered_nodes(), None);
+    }
+
+    #[test]
+    fn palette_choice_is_undoable() {
+        let mut graph = Graph::new();
+        let outcome = graph
+            .apply_command(GraphCommand::SetPalette {
+                name: "rainbow".into(),
+            })
+            .unwrap();
+        assert_eq!(
+            outcome.message,
+            "hashtags now coloured from the rainbow palette"
+        );
+        assert_eq!(graph.palette(), Some("rainbow"));
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(graph.palette(), None);
+    }
+
+    #[test]
+    fn counts_nodes_by_hashtag() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "Frodo #hobbit #ring");
+        insert(&mut graph, "Sam #hobbit #hobbit");
+        insert(&mut graph, "Gandalf #wizard");
+        insert(&mut graph, "Bree");
+
+        let counts: Vec<_> = graph
+            .hashtag_counts()
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("#hobbit".to_string(), 2),
+                ("#ring".to_string(), 1),
+                ("#wizard".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn legend_choice_is_undoable() {
+        let mut graph = Graph::new();
+        let outcome = graph
+            .apply_command(GraphCommand::SetLegend { show_legend: true })
+            .unwrap();
+        assert_eq!(outcome.message, "legend on");
+        assert!(graph.show_legend());
+
+        graph.apply_command(GraphCommand::Undo).unwrap();
+        assert!(!graph.show_legend());
+    }
+
+    #[test]
+    fn history_survives_export_and_import() {
+        let mut graph = Graph::new();
+        insert(&mut graph, "a");
+        insert(&mut graph, "b");
+        let history = graph.export_history();
+
+        let mut restored = Graph::new();
+        restored.restore(graph.snapshot());
+        restored.import_history(&history).unwrap();
+
+        restored.apply_command(GraphCommand::Undo).unwrap();
+        assert_eq!(restored.nodes.len(), 1);
+    }
 }
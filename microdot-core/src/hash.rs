@@ -1,6 +1,7 @@
 use regex::Regex;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 
 #[derive(PartialEq, Eq, Debug)]
@@ -20,9 +21,9 @@ impl HashTag {
     }
 }
 
-impl ToString for HashTag {
-    fn to_string(&self) -> String {
-        self.tag.clone()
+impl Display for HashTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.tag)
     }
 }
 
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// A saved state, along with a description of the change that moved the graph away from it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry<T> {
+    pub description: String,
+    pub state: T,
+}
+
+/// Bounded undo and redo stacks. Recording a new change clears anything that could have been
+/// redone, and once the limit is reached the oldest undo entries are dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History<T> {
+    limit: usize,
+    undo: VecDeque<HistoryEntry<T>>,
+    redo: Vec<HistoryEntry<T>>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        Self::with_limit(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl<T> History<T> {
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit,
+            undo: VecDeque::new(),
+            redo: vec![],
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.truncate();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// remember `state`, the state before the change described by `description`.
+    pub fn record<S: Into<String>>(&mut self, description: S, state: T) {
+        self.redo.clear();
+        self.undo.push_back(HistoryEntry {
+            description: description.into(),
+            state,
+        });
+        self.truncate();
+    }
+
+    /// swap `current` for the most recently recorded state, keeping `current` so it can be redone.
+    pub fn undo(&mut self, current: T) -> Option<HistoryEntry<T>> {
+        let entry = self.undo.pop_back()?;
+        self.redo.push(HistoryEntry {
+            description: entry.description.clone(),
+            state: current,
+        });
+        Some(entry)
+    }
+
+    /// swap `current` for the most recently undone state, keeping `current` so it can be undone.
+    pub fn redo(&mut self, current: T) -> Option<HistoryEntry<T>> {
+        let entry = self.redo.pop()?;
+        self.undo.push_back(HistoryEntry {
+            description: entry.description.clone(),
+            state: current,
+        });
+        self.truncate();
+        Some(entry)
+    }
+
+    fn truncate(&mut self) {
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undoes_and_redoes_in_order() {
+        let mut history = History::default();
+        history.record("one", 0);
+        history.record("two", 1);
+
+        let undone = history.undo(2).unwrap();
+        assert_eq!(undone.state, 1);
+        assert_eq!(undone.description, "two");
+
+        let undone = history.undo(1).unwrap();
+        assert_eq!(undone.state, 0);
+        assert!(history.undo(0).is_none());
+
+        let redone = history.redo(0).unwrap();
+        assert_eq!(redone.state, 1);
+        assert_eq!(redone.description, "one");
+    }
+
+    #[test]
+    fn recording_clears_redo() {
+        let mut history = History::default();
+        history.record("one", 0);
+        history.undo(1);
+        assert!(history.can_redo());
+
+        history.record("two", 0);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn drops_oldest_entries_past_the_limit() {
+        let mut history = History::with_limit(2);
+        history.record("one", 0);
+        history.record("two", 1);
+        history.record("three", 2);
+
+        assert_eq!(history.undo(3).unwrap().state, 2);
+        assert_eq!(history.undo(2).unwrap().state, 1);
+        assert!(history.undo(1).is_none());
+    }
+}
@@ -1,7 +1,9 @@
 pub mod command;
+pub mod error;
 pub mod exporter;
 pub mod graph;
 pub mod hash;
+pub mod history;
 
 macro_rules! new_string_type {
     ($id: ident) => {
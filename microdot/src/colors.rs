@@ -1,8 +1,11 @@
-use crate::palettes::PaletteReader;
+use crate::palettes::{palettes, DEFAULT_PALETTE};
 use palette::*;
+use serde::Deserialize;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
 pub struct Color {
     inner: Srgb<u8>,
 }
@@ -20,6 +23,11 @@ impl Color {
         }
     }
 
+    /// the colour as the palette crate has it, for converting to other colour spaces.
+    pub fn to_srgb(&self) -> Srgb {
+        self.inner.into_format()
+    }
+
     fn r(&self) -> u8 {
         self.inner.red
     }
@@ -49,6 +57,37 @@ impl Color {
         format!("#{}", s)
     }
 
+    /// how bright the colour looks, as WCAG defines it; 0 for black up to 1 for white.
+    pub fn relative_luminance(&self) -> f64 {
+        fn linear(component: u8) -> f64 {
+            let c = component as f64 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        }
+
+        0.2126 * linear(self.r()) + 0.7152 * linear(self.g()) + 0.0722 * linear(self.b())
+    }
+
+    /// the WCAG contrast ratio between two colours; 1 when they're the same, up to 21 for black
+    /// on white. It doesn't matter which way round they are.
+    pub fn contrast_ratio(&self, other: &Color) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// black or white, whichever is easier to read on this colour.
+    pub fn text_color(&self) -> Color {
+        let (black, white) = (Colors::black(), Colors::white());
+        if self.contrast_ratio(&black) >= self.contrast_ratio(&white) {
+            black
+        } else {
+            white
+        }
+    }
+
     pub fn from_html_string(html_str: &str) -> Result<Color, anyhow::Error> {
         if html_str.len() != 7 {
             return Err(anyhow::Error::msg(
@@ -72,6 +111,14 @@ impl Color {
     }
 }
 
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(html_str: String) -> Result<Self, Self::Error> {
+        Color::from_html_string(&html_str)
+    }
+}
+
 pub struct Colors;
 
 impl Colors {
@@ -91,9 +138,6 @@ pub struct ColorScheme {
     node_border_width: f64,
 }
 
-// const PALETTE_NAME: &str = "antarctica_evening_v2";
-const PALETTE_NAME: &str = "generated";
-
 impl ColorScheme {
     const NODE_BORDER_WIDTH: f64 = 3.0f64;
 
@@ -106,11 +150,12 @@ impl ColorScheme {
         }
     }
 
-    fn from_entry(i: usize) -> Self {
-        let content = include_str!("./palettes.txt");
-        let reader = PaletteReader {};
-        let palettes = reader.read(content).expect("couldn't read palette");
-        let palette = palettes.get(PALETTE_NAME).unwrap();
+    fn from_entry(palette: &str, i: usize) -> Self {
+        // a graph saved with a palette which isn't here any more gets the default one.
+        let palette = palettes()
+            .get(palette)
+            .or_else(|| palettes().get(DEFAULT_PALETTE))
+            .expect("the default palette is built in");
 
         let stroke_color = palette.get_stroke_color();
         let fill_color = palette.get_fill_color(i);
@@ -123,7 +168,12 @@ impl ColorScheme {
     }
 
     pub fn series(highlight: usize) -> Self {
-        ColorScheme::from_entry(highlight)
+        ColorScheme::from_entry(DEFAULT_PALETTE, highlight)
+    }
+
+    /// like `series`, but from the named palette, or the default one if that's None.
+    pub fn series_in(palette: Option<&str>, highlight: usize) -> Self {
+        ColorScheme::from_entry(palette.unwrap_or(DEFAULT_PALETTE), highlight)
     }
 
     pub fn get_stroke_color(&self) -> Color {
@@ -168,4 +218,29 @@ mod tests {
             assert!(Color::from_html_string(naughty).is_err());
         }
     }
+
+    #[test]
+    fn measures_contrast_the_wcag_way() {
+        assert_eq!(Colors::black().relative_luminance(), 0.0);
+        assert_eq!(Colors::white().relative_luminance(), 1.0);
+        assert_eq!(Colors::black().contrast_ratio(&Colors::white()), 21.0);
+        assert_eq!(Colors::white().contrast_ratio(&Colors::black()), 21.0);
+
+        let teal = Color::from_html_string("#177C99").unwrap();
+        assert!((teal.contrast_ratio(&Colors::white()) - 4.8).abs() < 0.05);
+    }
+
+    #[test]
+    fn picks_readable_text_colors() {
+        let dark = ["#177C99", "#446E1C", "#202060"];
+        let light = ["#FFFFFF", "#EADE84", "#D0CCCC", "#ED4145"];
+        for code in &dark {
+            let color = Color::from_html_string(code).unwrap();
+            assert_eq!(color.text_color(), Colors::white(), "{}", code);
+        }
+        for code in &light {
+            let color = Color::from_html_string(code).unwrap();
+            assert_eq!(color.text_color(), Colors::black(), "{}", code);
+        }
+    }
 }
@@ -0,0 +1,422 @@
+use anyhow::anyhow;
+use microdot_core::graph::Graph;
+use microdot_core::Label;
+use pom::char_class::{alpha, alphanum, digit};
+use pom::parser::*;
+use std::collections::HashMap;
+
+/// Reads a graphviz `digraph` into a graph. Nodes are given fresh microdot ids, and only the
+/// parts microdot understands are kept; node and edge labels, and `rankdir`.
+pub struct DotImporter {
+    content: String,
+}
+
+type Attributes = Vec<(String, String)>;
+
+#[derive(Debug)]
+enum Statement {
+    /// `rankdir=LR`, outside any attribute list
+    Assignment(String, String),
+    /// `graph [...]`, `node [...]` or `edge [...]`
+    Defaults(String, Attributes),
+    Node(String, Attributes),
+    Edge(Vec<Endpoint>, Attributes),
+    Subgraph(Vec<Statement>),
+}
+
+#[derive(Debug)]
+enum Endpoint {
+    Node(String),
+    Subgraph(Vec<Statement>),
+}
+
+/// nodes in the order they were first mentioned, and edges between them.
+#[derive(Default)]
+struct Collected {
+    is_left_right: bool,
+    nodes: Vec<(String, Option<String>)>,
+    edges: Vec<(String, String, Option<String>)>,
+}
+
+impl DotImporter {
+    pub fn new<S: Into<String>>(content: S) -> Self {
+        DotImporter {
+            content: content.into(),
+        }
+    }
+
+    pub fn import(&self) -> Result<Graph, anyhow::Error> {
+        let content = without_preprocessor_lines(&self.content);
+        let statements = dot_graph()
+            .parse(content.as_bytes())
+            .map_err(|e| anyhow!("could not parse dot: {}", e))?;
+
+        let mut collected = Collected::default();
+        collect(&statements, &mut collected);
+
+        let mut graph = Graph::new();
+        graph.set_direction(collected.is_left_right);
+
+        let mut ids = HashMap::new();
+        for (name, label) in collected.nodes {
+            let label = match label {
+                // graphviz's way of saying 'the node name'
+                Some(label) if label != "\\N" => label,
+                _ => name.clone(),
+            };
+            let (id, _) = graph.insert_node(Label::new(clean_label(&label)));
+            ids.insert(name, id);
+        }
+
+        for (from, to, label) in collected.edges {
+            let label = label.map(|l| Label::new(clean_label(&l)));
+            graph.link_edge(&ids[&from], &ids[&to], label)?;
+        }
+
+        Ok(graph)
+    }
+}
+
+fn collect(statements: &[Statement], collected: &mut Collected) {
+    for statement in statements {
+        match statement {
+            Statement::Assignment(key, value) => collect_graph_attribute(key, value, collected),
+            Statement::Defaults(kind, attributes) if kind == "graph" => {
+                for (key, value) in attributes {
+                    collect_graph_attribute(key, value, collected);
+                }
+            }
+            Statement::Defaults(..) => {}
+            Statement::Node(name, attributes) => {
+                mention_node(name, collected);
+                if let Some(label) = find_label(attributes) {
+                    if let Some((_, existing)) = collected.nodes.iter_mut().find(|(n, _)| n == name)
+                    {
+                        *existing = Some(label);
+                    }
+                }
+            }
+            Statement::Edge(endpoints, attributes) => {
+                let label = find_label(attributes);
+                let endpoints: Vec<_> = endpoints
+                    .iter()
+                    .map(|endpoint| endpoint_nodes(endpoint, collected))
+                    .collect();
+                for pair in endpoints.windows(2) {
+                    for from in &pair[0] {
+                        for to in &pair[1] {
+                            collected
+                                .edges
+                                .push((from.clone(), to.clone(), label.clone()));
+                        }
+                    }
+                }
+            }
+            Statement::Subgraph(statements) => collect(statements, collected),
+        }
+    }
+}
+
+/// the nodes an edge connects to at one end; every node in it, for a subgraph.
+fn endpoint_nodes(endpoint: &Endpoint, collected: &mut Collected) -> Vec<String> {
+    match endpoint {
+        Endpoint::Node(name) => {
+            mention_node(name, collected);
+            vec![name.clone()]
+        }
+        Endpoint::Subgraph(statements) => {
+            let before = collected.nodes.len();
+            collect(statements, collected);
+            let mut names = vec![];
+            for statement in statements {
+                if let Statement::Node(name, _) = statement {
+                    names.push(name.clone());
+                }
+            }
+            // nodes first mentioned by edges inside the subgraph count too
+            for (name, _) in &collected.nodes[before..] {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            names
+        }
+    }
+}
+
+fn collect_graph_attribute(key: &str, value: &str, collected: &mut Collected) {
+    if key == "rankdir" {
+        collected.is_left_right = matches!(value, "LR" | "RL");
+    }
+}
+
+fn mention_node(name: &str, collected: &mut Collected) {
+    if !collected.nodes.iter().any(|(n, _)| n == name) {
+        collected.nodes.push((name.to_string(), None));
+    }
+}
+
+fn find_label(attributes: &Attributes) -> Option<String> {
+    attributes
+        .iter()
+        .rev()
+        .find(|(key, _)| key == "label")
+        .map(|(_, value)| value.clone())
+}
+
+/// labels are one line in microdot, so graphviz line breaks become spaces.
+fn clean_label(label: &str) -> String {
+    let label = label
+        .replace("\\n", " ")
+        .replace("\\l", " ")
+        .replace("\\r", " ");
+    label.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn to_string(u8s: Vec<u8>) -> String {
+    String::from_utf8(u8s).expect("can only parse utf")
+}
+
+/// graphviz throws away lines starting with '#', as C preprocessor output; it's only a comment
+/// there, not wherever whitespace goes. They're blanked rather than dropped to keep the lines.
+fn without_preprocessor_lines(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| if line.starts_with('#') { "" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn comment<'a>() -> Parser<'a, u8, ()> {
+    let line = seq(b"//") * none_of(b"\n").repeat(0..);
+    let block = seq(b"/*") * (!seq(b"*/") * any()).repeat(0..) * seq(b"*/");
+    (line.discard() | block.discard()).name("comment")
+}
+
+/// whitespace and comments
+fn space<'a>() -> Parser<'a, u8, ()> {
+    (one_of(b" \t\r\n").discard() | comment())
+        .repeat(0..)
+        .discard()
+}
+
+fn symbol<'a>(s: &'static [u8]) -> Parser<'a, u8, ()> {
+    (space() * seq(s) - space()).discard()
+}
+
+/// a keyword like `node`, which isn't the start of a longer name like `nodes`
+fn keyword<'a>(s: &'static [u8]) -> Parser<'a, u8, String> {
+    let word = seq(s) - !is_a(is_name_char);
+    (space() * word - space()).map(|s| to_string(s.to_vec()))
+}
+
+fn is_name_start(c: u8) -> bool {
+    alpha(c) || c == b'_' || c >= 0x80
+}
+
+fn is_name_char(c: u8) -> bool {
+    alphanum(c) || c == b'_' || c >= 0x80
+}
+
+fn name<'a>() -> Parser<'a, u8, String> {
+    (is_a(is_name_start) + is_a(is_name_char).repeat(0..)).map(|(first, mut rest)| {
+        rest.insert(0, first);
+        to_string(rest)
+    })
+}
+
+fn numeral<'a>() -> Parser<'a, u8, String> {
+    let digits = is_a(digit).repeat(1..);
+    let number = (digits + (sym(b'.') * is_a(digit).repeat(0..)).opt()).discard()
+        | (sym(b'.') * is_a(digit).repeat(1..)).discard();
+    (sym(b'-').opt() * number)
+        .collect()
+        .map(|u8s| to_string(u8s.to_vec()))
+}
+
+fn quoted<'a>() -> Parser<'a, u8, String> {
+    let escaped_quote = seq(b"\\\"").map(|_| b'"');
+    let inner = (escaped_quote | none_of(b"\"")).repeat(0..);
+    (sym(b'"') * inner - sym(b'"')).map(to_string)
+}
+
+/// `<...>` html labels; the tags are dropped, leaving the text.
+fn html<'a>() -> Parser<'a, u8, String> {
+    (sym(b'<') * call(html_content) - sym(b'>')).map(|content| strip_tags(&content))
+}
+
+fn html_content<'a>() -> Parser<'a, u8, String> {
+    let nested = (sym(b'<') * call(html_content) - sym(b'>')).map(|inner| format!("<{}>", inner));
+    let text = none_of(b"<>").repeat(1..).map(to_string);
+    (nested | text).repeat(0..).map(|parts| parts.concat())
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+fn dot_id<'a>() -> Parser<'a, u8, String> {
+    space() * (quoted() | html() | name() | numeral()) - space()
+}
+
+/// `a` or `a:port`; the port is ignored
+fn node_id<'a>() -> Parser<'a, u8, String> {
+    dot_id() - (sym(b':') * dot_id()).repeat(0..2)
+}
+
+fn attributes<'a>() -> Parser<'a, u8, Attributes> {
+    let pair = dot_id() - symbol(b"=") + dot_id() - (symbol(b",") | symbol(b";")).opt();
+    let list = symbol(b"[") * pair.repeat(0..) - symbol(b"]");
+    list.repeat(1..).map(|lists| lists.concat())
+}
+
+fn subgraph<'a>() -> Parser<'a, u8, Vec<Statement>> {
+    let header = keyword(b"subgraph") * dot_id().opt();
+    header.opt() * symbol(b"{") * call(statements) - symbol(b"}")
+}
+
+fn edge_op<'a>() -> Parser<'a, u8, ()> {
+    symbol(b"->") | symbol(b"--")
+}
+
+fn endpoint<'a>() -> Parser<'a, u8, Endpoint> {
+    subgraph().map(Endpoint::Subgraph) | node_id().map(Endpoint::Node)
+}
+
+fn statement<'a>() -> Parser<'a, u8, Statement> {
+    let defaults = (keyword(b"graph") | keyword(b"node") | keyword(b"edge")) + attributes();
+    let assignment = dot_id() - symbol(b"=") + dot_id();
+    let edge = (endpoint() + (edge_op() * endpoint()).repeat(1..) + attributes().opt()).map(
+        |((first, mut rest), attributes)| {
+            rest.insert(0, first);
+            Statement::Edge(rest, attributes.unwrap_or_default())
+        },
+    );
+    let node = node_id() + attributes().opt();
+
+    defaults.map(|(kind, attributes)| Statement::Defaults(kind, attributes))
+        | edge
+        | subgraph().map(Statement::Subgraph)
+        | assignment.map(|(key, value)| Statement::Assignment(key, value))
+        | node.map(|(name, attributes)| Statement::Node(name, attributes.unwrap_or_default()))
+}
+
+fn statements<'a>() -> Parser<'a, u8, Vec<Statement>> {
+    (statement() - symbol(b";").opt()).repeat(0..)
+}
+
+fn dot_graph<'a>() -> Parser<'a, u8, Vec<Statement>> {
+    let header = keyword(b"strict").opt() * (keyword(b"digraph") | keyword(b"graph"));
+    space() * header * dot_id().opt() * symbol(b"{") * statements() - symbol(b"}") - end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::JsonExporter;
+    use microdot_core::Id;
+
+    #[test]
+    fn imports_nodes_edges_and_labels() {
+        let content = r#"
+            // the build
+            digraph deps {
+                rankdir="LR";
+                node [shape=box, fontname="Helvetica"];
+
+                core [label="microdot\ncore"];
+                cli -> core [label="uses"];
+                "web ui" -> cli -> core;
+                /* unlabelled nodes keep their names */
+                cli -> { parser; repl }
+            }
+        "#;
+
+        let graph = DotImporter::new(content)
+            .import()
+            .expect("could not import");
+
+        assert_eq!(
+            graph.find_node_label(&Id::new("n0")),
+            Some(Label::new("microdot core"))
+        );
+        assert_eq!(
+            graph.find_node_label(&Id::new("n2")),
+            Some(Label::new("web ui"))
+        );
+
+        let json = JsonExporter::new().export_json(&graph);
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["is_left_right"], true);
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 5);
+
+        let edges: Vec<_> = json["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                format!(
+                    "{}->{}{}",
+                    e["from"].as_str().unwrap(),
+                    e["to"].as_str().unwrap(),
+                    e["label"]
+                        .as_str()
+                        .map(|l| format!(" {}", l))
+                        .unwrap_or_default()
+                )
+            })
+            .collect();
+        assert_eq!(
+            edges,
+            vec!["n1->n0 uses", "n2->n1", "n1->n0", "n1->n3", "n1->n4"]
+        );
+    }
+
+    #[test]
+    fn imports_html_labels() {
+        let content = r#"digraph { a [label=<<b>bold</b> move>]; b; a -> b }"#;
+        let graph = DotImporter::new(content)
+            .import()
+            .expect("could not import");
+        assert_eq!(
+            graph.find_node_label(&Id::new("n0")),
+            Some(Label::new("bold move"))
+        );
+    }
+
+    #[test]
+    fn skips_preprocessor_lines() {
+        let content = "# 1 \"deps.gv\"\ndigraph {\n# 2\n    a -> b\n}\n";
+        let graph = DotImporter::new(content)
+            .import()
+            .expect("could not import");
+        assert_eq!(graph.find_node_label(&Id::new("n1")), Some(Label::new("b")));
+
+        // but '#' isn't a comment anywhere else
+        assert!(DotImporter::new("digraph {\n    a -> b # not a comment\n}")
+            .import()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_dot() {
+        assert!(DotImporter::new("digraph { a -> }").import().is_err());
+        assert!(DotImporter::new("not a graph").import().is_err());
+    }
+}
@@ -1,4 +1,3 @@
-use crate::graphviz::DisplayMode;
 use fdg_img::{
     style::{
         text_anchor::{HPos, Pos, VPos},
@@ -9,7 +8,7 @@ use fdg_img::{
 use fdg_sim::glam::Vec3;
 use fdg_sim::petgraph::stable_graph::NodeIndex;
 use fdg_sim::{ForceGraph, ForceGraphHelper};
-use microdot_core::exporter::{Exporter, NodeHighlight};
+use microdot_core::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
 use microdot_core::graph::Graph;
 use microdot_core::{Id, Label};
 use std::collections::HashMap;
@@ -18,7 +17,6 @@ pub struct FdgExporter {
     inner_content: ForceGraph<(), ()>,
     node_map: HashMap<Id, NodeIndex>,
     is_left_right: bool,
-    display_mode: DisplayMode,
 }
 
 impl Default for FdgExporter {
@@ -27,7 +25,6 @@ impl Default for FdgExporter {
             inner_content: ForceGraph::default(),
             node_map: HashMap::new(),
             is_left_right: true,
-            display_mode: DisplayMode::Interactive,
         }
     }
 }
@@ -62,12 +59,7 @@ impl FdgExporter {
             .add_force_node_with_coords("end", (), end_coords);
         this.inner_content[end_ni].locked = true;
 
-        let FdgExporter {
-            inner_content,
-            node_map,
-            is_left_right,
-            display_mode,
-        } = this;
+        let FdgExporter { inner_content, .. } = this;
         // generate svg text for your graph
         let g: fdg_sim::ForceGraph<(), ()> = inner_content;
 
@@ -96,15 +88,34 @@ impl Exporter for FdgExporter {
         self.is_left_right = is_left_right;
     }
 
-    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight) {
+    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight, _role: NodeRole) {
         let ni = self.inner_content.add_force_node(label.to_string(), ());
 
         self.node_map.insert(id.clone(), ni);
     }
 
-    fn add_edge(&mut self, id: &Id, from: &Id, to: &Id) {
+    fn add_edge(
+        &mut self,
+        _id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        _highlight: EdgeHighlight,
+    ) {
         let from_ni = self.node_map[from];
         let to_ni = self.node_map[to];
-        self.inner_content.add_edge(from_ni, to_ni, ());
+
+        match label {
+            // fdg only draws names on nodes, so route labelled edges through a node carrying the
+            // label.
+            Some(label) => {
+                let label_ni = self.inner_content.add_force_node(label.to_string(), ());
+                self.inner_content.add_edge(from_ni, label_ni, ());
+                self.inner_content.add_edge(label_ni, to_ni, ());
+            }
+            None => {
+                self.inner_content.add_edge(from_ni, to_ni, ());
+            }
+        }
     }
 }
@@ -1,11 +1,12 @@
 use crate::colors::{Color, ColorScheme, Colors};
+use crate::styles::{StyleRules, TagStyle};
 use anyhow::{anyhow, Context, Result};
 use askama::Template;
 use command_macros::cmd;
 use hyphenation::{Language, Load, Standard};
-use microdot_core::exporter::{Exporter, NodeHighlight};
+use microdot_core::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
 use microdot_core::graph::Graph;
-use microdot_core::hash::extract_hashtags;
+use microdot_core::hash::{extract_hashtags, HashTag};
 use microdot_core::{Id, Label};
 use once_cell::sync::OnceCell;
 use regex::Regex;
@@ -120,7 +121,7 @@ fn compile_dot_str<S: AsRef<str>>(
 }
 pub fn compile(path: &Path, _display_mode: DisplayMode, format: OutputFormat) -> Result<()> {
     let input_str = std::fs::read_to_string(path)?;
-    let out_file = path.with_extension(&format.to_string());
+    let out_file = path.with_extension(format.to_string());
 
     compile_dot_str(input_str, _display_mode, format).and_then(|string| {
         std::fs::write(out_file, string)?;
@@ -133,6 +134,22 @@ pub struct GraphVizExporter {
     is_left_right: bool,
     is_first_edge: bool,
     display_mode: DisplayMode,
+    /// draw root causes and terminal effects differently to the nodes between them.
+    show_roles: bool,
+    /// hashtags whose nodes are drawn together in a box of the tag's colour.
+    clusters: Vec<Cluster>,
+    styles: StyleRules,
+    /// the graph's palette, for colouring hashtags
+    palette: Option<String>,
+    /// the graph's choice of a key to the hashtags' colours, with how many nodes use each.
+    show_legend: bool,
+}
+
+struct Cluster {
+    /// the hashtag, including the '#'
+    tag: String,
+    /// the lines for the nodes inside it
+    content: String,
 }
 
 fn template(template_str: &str, variables: &HashMap<&str, String>) -> String {
@@ -149,16 +166,7 @@ impl Exporter for GraphVizExporter {
         self.is_left_right = is_left_right;
     }
 
-    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight) {
-        // TODO: probably horrific perf.
-
-        let wrap_size = if self.is_left_right { 40 } else { 25 };
-        let wrapping_options = {
-            let dictionary = Standard::from_embedded(Language::EnglishUS).unwrap();
-            let splitter = WordSplitter::Hyphenation(dictionary);
-            Options::new(wrap_size).word_splitter(splitter)
-        };
-
+    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight, role: NodeRole) {
         let base_label = &label.to_string();
 
         let (hash_tags, label_text) = extract_hashtags(base_label);
@@ -168,19 +176,20 @@ impl Exporter for GraphVizExporter {
             DisplayMode::Presentation => "".to_string(),
         };
 
-        let label_text = fill(&label_text, &wrapping_options);
+        let label_text = wrap_label(&label_text, self.is_left_right);
 
-        let bgcolor = match highlight {
-            NodeHighlight::Normal => Colors::white(),
-            NodeHighlight::SearchResult => Color::from_rgb(208, 204, 204),
-            NodeHighlight::CurrentNode => Colors::white(),
-        };
+        let bgcolor = highlight_color(highlight);
+        let style = self.styles.for_tags(&hash_tags);
 
         let hash_tags: Vec<_> = hash_tags
             .iter()
-            .map(|tag| HashTagViewModel {
-                label: tag.to_string(),
-                bgcolor: ColorScheme::series(tag.hash()).get_fill_color(),
+            .map(|tag| {
+                let bgcolor = self.hashtag_color(tag);
+                HashTagViewModel {
+                    label: tag.to_string(),
+                    bgcolor,
+                    font_color: bgcolor.text_color(),
+                }
             })
             .collect();
 
@@ -197,35 +206,76 @@ impl Exporter for GraphVizExporter {
             hash_tags,
             colspan,
             bgcolor,
+            font_color: bgcolor.text_color(),
+            role_attributes: if self.show_roles {
+                role_attributes(role)
+            } else {
+                ""
+            },
+            style_attributes: style_attributes(&style),
         };
 
         let line = label_vm.render().unwrap();
 
-        self.inner_content.push_str(&line);
-        self.inner_content.push('\n');
+        // a node with several clustered tags goes in the first one asked for
+        let cluster = self.clusters.iter_mut().find(|cluster| {
+            label_vm
+                .hash_tags
+                .iter()
+                .any(|tag| tag.label == cluster.tag)
+        });
+        let content = match cluster {
+            Some(cluster) => &mut cluster.content,
+            None => &mut self.inner_content,
+        };
+
+        content.push_str(&line);
+        content.push('\n');
     }
 
-    fn add_edge(&mut self, id: &Id, from: &Id, to: &Id) {
+    fn add_edge(
+        &mut self,
+        id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        highlight: EdgeHighlight,
+    ) {
         if self.is_first_edge {
             self.inner_content.push('\n');
             self.is_first_edge = false;
         }
 
+        let label = label.map(|l| l.to_string());
+        let id_and_label = label.as_ref().map(|l| format!("{}: {}", id, l));
+
         let edge_params = hashmap! {
             "id" => id.to_string(),
             "escaped_id" => escape_id(id.to_string()),
             "escaped_from" => escape_id(from.to_string()),
             "escaped_to" => escape_id(to.to_string()),
+            "escaped_label" => to_dot_label_string(label.as_deref().unwrap_or_default()),
+            "escaped_id_and_label" => to_dot_label_string(id_and_label.as_deref().unwrap_or_default()),
+            "highlight" => edge_highlight_attributes(highlight),
         };
 
-        let line = match self.display_mode {
-            DisplayMode::Interactive => template(
-                r#"    ${escaped_from} -> ${escaped_to} [label=${escaped_id}];"#,
+        let line = match (self.display_mode, label) {
+            (DisplayMode::Interactive, None) => template(
+                r#"    ${escaped_from} -> ${escaped_to} [label=${escaped_id}]${highlight};"#,
+                &edge_params,
+            ),
+            (DisplayMode::Interactive, Some(_)) => template(
+                r#"    ${escaped_from} -> ${escaped_to} [label=${escaped_id_and_label}]${highlight};"#,
+                &edge_params,
+            ),
+            (DisplayMode::Presentation, None) => template(
+                r#"    ${escaped_from} -> ${escaped_to}${highlight};"#,
+                &edge_params,
+            ),
+            (DisplayMode::Presentation, Some(_)) => template(
+                r#"    ${escaped_from} -> ${escaped_to} [label=${escaped_label}]${highlight};"#,
                 &edge_params,
             ),
-            DisplayMode::Presentation => {
-                template(r#"    ${escaped_from} -> ${escaped_to};"#, &edge_params)
-            }
         };
 
         self.inner_content.push_str(&line);
@@ -240,12 +290,112 @@ impl GraphVizExporter {
             is_left_right: false,
             is_first_edge: true,
             display_mode,
+            show_roles: false,
+            clusters: vec![],
+            styles: StyleRules::default(),
+            palette: None,
+            show_legend: false,
+        }
+    }
+
+    /// roots get rounded corners, leaves a double border, and unlinked nodes both.
+    pub fn show_roles(mut self, show_roles: bool) -> Self {
+        self.show_roles = show_roles;
+        self
+    }
+
+    /// group the nodes carrying each of these hashtags, given without the '#', into a cluster
+    /// coloured like the tag.
+    pub fn clusters(mut self, tags: &[String]) -> Self {
+        self.clusters = tags
+            .iter()
+            .map(|tag| Cluster {
+                tag: format!("#{}", tag),
+                content: String::new(),
+            })
+            .collect();
+        self
+    }
+
+    /// draw the nodes carrying these hashtags the way the rules say.
+    pub fn styles(mut self, styles: &StyleRules) -> Self {
+        self.styles = styles.clone();
+        self
+    }
+
+    /// the colour the rules give a hashtag, or one from the palette if they don't.
+    fn hashtag_color(&self, tag: &HashTag) -> Color {
+        self.styles
+            .get(tag)
+            .and_then(|style| style.fill)
+            .unwrap_or_else(|| {
+                ColorScheme::series_in(self.palette.as_deref(), tag.hash()).get_fill_color()
+            })
+    }
+
+    /// a cluster listing every hashtag in the graph with its colour and node count, so a
+    /// drawing makes sense to people who've never seen the graph's labels.
+    fn legend(&self, graph: &Graph) -> Option<String> {
+        let entries: Vec<_> = graph
+            .hashtag_counts()
+            .into_iter()
+            .map(|(tag, count)| {
+                let bgcolor = self.hashtag_color(&tag);
+                LegendEntryViewModel {
+                    tag: HashTagViewModel {
+                        label: tag.to_string(),
+                        bgcolor,
+                        font_color: bgcolor.text_color(),
+                    },
+                    count,
+                }
+            })
+            .collect();
+
+        if entries.is_empty() {
+            return None;
         }
+
+        let vm = LegendViewModel {
+            entries,
+            bgcolor: Colors::white(),
+            border_color: ColorScheme::normal().get_stroke_color(),
+        };
+        Some(vm.render().unwrap())
     }
 
     pub fn export_dot(&mut self, graph: &Graph) -> String {
+        self.palette = graph.palette().map(String::from);
+        self.show_legend = graph.show_legend();
         graph.export(self);
 
+        // the clusters go first, so every node is declared before the edges which use it
+        let mut clusters = String::new();
+        for cluster in self.clusters.iter().filter(|c| !c.content.is_empty()) {
+            let (parsed, _) = extract_hashtags(&cluster.tag);
+            let bgcolor = parsed
+                .first()
+                .map_or_else(Colors::white, |tag| self.hashtag_color(tag));
+            let vm = ClusterViewModel {
+                name: escape_id(format!("cluster_{}", &cluster.tag[1..])),
+                label: to_dot_label_string(&cluster.tag),
+                bgcolor,
+                font_color: bgcolor.text_color(),
+                inner_content: cluster.content.clone(),
+            };
+            clusters.push_str(&vm.render().unwrap());
+            clusters.push('\n');
+        }
+        self.inner_content.insert_str(0, &clusters);
+
+        if self.show_legend {
+            if let Some(legend) = self.legend(graph) {
+                self.inner_content.push('\n');
+                self.inner_content.push_str(&legend);
+                self.inner_content.push('\n');
+            }
+        }
+
         let rank_dir = if self.is_left_right { "LR" } else { "TB" };
         let rank_dir = rank_dir.to_string();
         let edge_color = ColorScheme::normal().get_stroke_color();
@@ -261,6 +411,74 @@ impl GraphVizExporter {
     }
 }
 
+/// wrap a label onto several lines, a little wider when the graph runs left to right.
+pub(crate) fn wrap_label(label_text: &str, is_left_right: bool) -> String {
+    // TODO: probably horrific perf.
+
+    let wrap_size = if is_left_right { 40 } else { 25 };
+    let wrapping_options = {
+        let dictionary = Standard::from_embedded(Language::EnglishUS).unwrap();
+        let splitter = WordSplitter::Hyphenation(dictionary);
+        Options::new(wrap_size).word_splitter(splitter)
+    };
+
+    fill(label_text, &wrapping_options)
+}
+
+fn role_attributes(role: NodeRole) -> &'static str {
+    match role {
+        NodeRole::Root => r#" style="filled,rounded""#,
+        NodeRole::Intermediate => "",
+        NodeRole::Leaf => " peripheries=2",
+        NodeRole::Isolated => r#" style="filled,rounded" peripheries=2"#,
+    }
+}
+
+/// the node attributes for the parts of a style which aren't about its hashtags' cells.
+fn style_attributes(style: &TagStyle) -> String {
+    let mut attributes = String::new();
+    if let Some(border) = style.border {
+        attributes.push_str(&format!(r#" color="{}""#, border));
+    }
+    if let Some(shape) = &style.shape {
+        attributes.push_str(&format!(" shape={}", to_dot_label_string(shape)));
+    }
+    if let Some(font) = &style.font {
+        attributes.push_str(&format!(" fontname={}", to_dot_label_string(font)));
+    }
+    if let Some(penwidth) = style.penwidth {
+        attributes.push_str(&format!(" penwidth={}", penwidth));
+    }
+    attributes
+}
+
+pub(crate) fn highlight_color(highlight: NodeHighlight) -> Color {
+    match highlight {
+        NodeHighlight::Normal => Colors::white(),
+        NodeHighlight::SearchResult => Color::from_rgb(208, 204, 204),
+        NodeHighlight::Reachable => Color::from_rgb(255, 229, 180),
+        NodeHighlight::OnPath => Color::from_rgb(255, 229, 180),
+        NodeHighlight::CurrentNode => Colors::white(),
+    }
+}
+
+pub(crate) fn edge_highlight_color(highlight: EdgeHighlight) -> Color {
+    match highlight {
+        EdgeHighlight::Normal => ColorScheme::normal().get_stroke_color(),
+        EdgeHighlight::OnPath => Color::from_rgb(224, 108, 0),
+    }
+}
+
+fn edge_highlight_attributes(highlight: EdgeHighlight) -> String {
+    match highlight {
+        EdgeHighlight::Normal => "".into(),
+        EdgeHighlight::OnPath => format!(
+            r#" [color="{}" penwidth=5]"#,
+            edge_highlight_color(highlight)
+        ),
+    }
+}
+
 fn to_dot_label_string(label: &str) -> String {
     format!("\"{}\"", label.replace('\n', "\\n").replace('"', "\\\""))
 }
@@ -307,6 +525,9 @@ struct NodeHtmlLabelViewModel {
     colspan: usize,
     hash_tags: Vec<HashTagViewModel>,
     bgcolor: Color,
+    font_color: Color,
+    role_attributes: &'static str,
+    style_attributes: String,
 }
 
 #[derive(Template)]
@@ -314,6 +535,30 @@ struct NodeHtmlLabelViewModel {
 struct HashTagViewModel {
     label: String,
     bgcolor: Color,
+    font_color: Color,
+}
+
+#[derive(Template)]
+#[template(path = "legend.txt")]
+struct LegendViewModel {
+    entries: Vec<LegendEntryViewModel>,
+    bgcolor: Color,
+    border_color: Color,
+}
+
+struct LegendEntryViewModel {
+    tag: HashTagViewModel,
+    count: usize,
+}
+
+#[derive(Template)]
+#[template(path = "cluster.txt")]
+struct ClusterViewModel {
+    name: String,
+    label: String,
+    bgcolor: Color,
+    font_color: Color,
+    inner_content: String,
 }
 
 #[derive(Template)]
@@ -329,10 +574,11 @@ struct GraphViewModel {
 mod tests {
     use super::*;
     use crate::util::{compile_input_string_content, git_root};
+    use microdot_core::graph::{Filter, PathCount, TagExpr};
 
     #[test]
     fn runs_node_template() {
-        let lines = vec![
+        let lines = [
             "this is the first",
             "line in the thing",
             "and here is a third",
@@ -344,20 +590,167 @@ mod tests {
             hash_tags: vec![
                 HashTagViewModel {
                     bgcolor: Color::from_rgb(255, 0, 0),
+                    font_color: Colors::black(),
                     label: "#hash1".into(),
                 },
                 HashTagViewModel {
                     bgcolor: Color::from_rgb(0, 255, 0),
+                    font_color: Colors::black(),
                     label: "#hash2".into(),
                 },
             ],
             colspan: 2,
             bgcolor: Colors::white(),
+            font_color: Colors::black(),
+            role_attributes: "",
+            style_attributes: "".into(),
         };
 
         println!("{}", label.render().unwrap());
     }
 
+    #[test]
+    fn exports_edge_labels() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("a"));
+        graph.insert_node(Label::new("b"));
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), Some(Label::new("causes")))
+            .unwrap();
+
+        let interactive = GraphVizExporter::new(DisplayMode::Interactive).export_dot(&graph);
+        assert!(interactive.contains(r#""n0" -> "n1" [label="e0: causes"];"#));
+
+        let presentation = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        assert!(presentation.contains(r#""n0" -> "n1" [label="causes"];"#));
+    }
+
+    #[test]
+    fn highlights_paths() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c"] {
+            graph.insert_node(Label::new(label));
+        }
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), None)
+            .unwrap();
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n2"), None)
+            .unwrap();
+        graph
+            .highlight_paths(&Id::new("n0"), &Id::new("n1"), PathCount::Shortest)
+            .unwrap();
+
+        let dot = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        assert!(dot.contains(r##""n0" -> "n1" [color="#E06C00" penwidth=5];"##));
+        assert!(dot.contains(r#""n0" -> "n2";"#));
+    }
+
+    #[test]
+    fn styles_roots_and_leaves_when_asked() {
+        let mut graph = Graph::new();
+        for label in ["cause", "effect", "alone"] {
+            graph.insert_node(Label::new(label));
+        }
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), None)
+            .unwrap();
+
+        let plain = GraphVizExporter::new(DisplayMode::Interactive).export_dot(&graph);
+        assert!(!plain.contains("rounded"));
+        assert!(!plain.contains("peripheries"));
+
+        let styled = GraphVizExporter::new(DisplayMode::Interactive)
+            .show_roles(true)
+            .export_dot(&graph);
+        // n0 is a root, n1 a leaf and n2 both
+        assert_eq!(styled.matches(r#"style="filled,rounded""#).count(), 2);
+        assert_eq!(styled.matches("peripheries=2").count(), 2);
+    }
+
+    #[test]
+    fn groups_tagged_nodes_into_clusters() {
+        let mut graph = Graph::new();
+        for label in [
+            "leaves the shire #Frodo",
+            "arrives #Gandalf #Frodo",
+            "at home #Bilbo",
+        ] {
+            graph.insert_node(Label::new(label));
+        }
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), None)
+            .unwrap();
+
+        let dot = GraphVizExporter::new(DisplayMode::Interactive)
+            .clusters(&[
+                "Gandalf".to_string(),
+                "Frodo".to_string(),
+                "Sam".to_string(),
+            ])
+            .export_dot(&graph);
+
+        // n1 has both tags, so it goes with the first one asked for; nobody is tagged #Sam
+        assert!(dot.contains(r#"subgraph "cluster_Gandalf" {"#));
+        assert!(dot.contains(r#"subgraph "cluster_Frodo" {"#));
+        assert!(!dot.contains("cluster_Sam"));
+        let gandalf = dot.find("cluster_Gandalf").unwrap();
+        let frodo = dot.find("cluster_Frodo").unwrap();
+        let n0 = dot.find("    n0 [label=").unwrap();
+        let n1 = dot.find("    n1 [label=").unwrap();
+        let n2 = dot.find("    n2 [label=").unwrap();
+        assert!(gandalf < n1 && n1 < frodo && frodo < n0);
+        assert!(n0 < n2 && n2 < dot.find(r#""n0" -> "n1""#).unwrap());
+    }
+
+    #[test]
+    fn draws_a_legend_of_hashtags() {
+        let mut graph = Graph::new();
+        for label in ["leaves the shire #Frodo", "arrives #Gandalf #Frodo", "Bree"] {
+            graph.insert_node(Label::new(label));
+        }
+
+        let dot = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        assert!(!dot.contains("cluster_legend"));
+
+        graph.set_show_legend(true);
+        let dot = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        assert!(dot.contains(r#"subgraph "cluster_legend" {"#));
+        assert!(dot.contains(">#Frodo</FONT></TD><TD>2</TD>"));
+        assert!(dot.contains(">#Gandalf</FONT></TD><TD>1</TD>"));
+
+        // it's a key to the whole graph, so the filter doesn't change it
+        graph.set_filter(Some(Filter {
+            expr: TagExpr::Tag("Gandalf".into()),
+            with_neighbours: false,
+        }));
+        let dot = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        assert!(dot.contains(">#Frodo</FONT></TD><TD>2</TD>"));
+    }
+
+    #[test]
+    fn styles_nodes_by_their_tags() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("storm coming #Risk"));
+        graph.insert_node(Label::new("all fine #Calm"));
+        let styles = StyleRules::parse(
+            r##"{ "#Risk": { "fill": "#ED4145", "border": "#800000", "shape": "octagon" } }"##,
+        )
+        .unwrap();
+
+        let dot = GraphVizExporter::new(DisplayMode::Interactive)
+            .styles(&styles)
+            .export_dot(&graph);
+
+        assert!(dot.contains(r##"<TD BGCOLOR="#ED4145">"##));
+        assert!(dot.contains(r##" color="#800000" shape="octagon"]"##));
+        // tags without a rule still get their palette colour
+        let (calm, _) = extract_hashtags("#Calm");
+        let calm = ColorScheme::series(calm[0].hash()).get_fill_color();
+        assert!(dot.contains(&format!(r#"<TD BGCOLOR="{}">"#, calm)));
+        assert_eq!(dot.matches("shape=").count(), 1);
+    }
+
     #[test]
     fn converts_to_dot_label_string() {
         assert_eq!(r#""abc""#, to_dot_label_string("abc"));
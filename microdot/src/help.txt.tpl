@@ -8,4 +8,13 @@ Usage:
 
   - run the app to build a graph. It'll save to graph.json, and load from that file next time.
   - if you've got graphviz installed, it'll save an svg to the same dir as your graph file with the .svg extension.
+  - add '--persist-undo' to keep the undo history in graph.undo.json, so you can still undo after a restart.
+  - run commands from a file with '--script cmds.txt', or pipe them in; add '--dry-run' to try them without saving, or '--stop-on-error' to stop at the first failure.
+  - bring in an existing graphviz file with 'microdot import deps.dot', which saves deps.json alongside it.
+  - without graphviz, it'll draw the svg itself; choose explicitly with '--render graphviz', '--render layered' or '--render fdg'.
+  - add '--show-roles' to draw root causes with rounded corners and end effects with a double border.
+  - add '--mermaid' to also save a graph.mmd mermaid flowchart of the whole graph, for pasting into markdown.
+  - add '--serve 8080' to watch the graph in your browser at http://127.0.0.1:8080/, redrawn after every change.
+  - pick the colours for hashtags with 'palette'; add your own as .txt files like src/palettes.txt, or Khroma .json downloads, in the palettes folder of the microdot config directory, or the folder given by '--palettes'.
+  - style tagged nodes with a graph.styles.json file next to your graph, or styles.json in the microdot config directory, eg { "#Risk": { "fill": "#ED4145", "border": "#800000", "shape": "octagon", "font": "Courier", "penwidth": 2 } }.
   - if you've got an auto-reloading svg viewer, use it! eg https://apps.apple.com/app/coteditor/id768053424
\ No newline at end of file
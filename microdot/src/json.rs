@@ -1,9 +1,8 @@
-use microdot_core::exporter::{Exporter, NodeHighlight};
+use microdot_core::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
 use microdot_core::graph::Graph;
 use microdot_core::{Id, Label};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct JsonExporter {
@@ -17,7 +16,7 @@ impl Exporter for JsonExporter {
         self.is_left_right = is_left_right;
     }
 
-    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight) {
+    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight, _role: NodeRole) {
         let node = json!({
             "id": id.to_string(),
             "label": label.to_string()
@@ -26,12 +25,24 @@ impl Exporter for JsonExporter {
         self.nodes.push(node);
     }
 
-    fn add_edge(&mut self, _id: &Id, from: &Id, to: &Id) {
-        let edge = json! { {
+    fn add_edge(
+        &mut self,
+        id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        _highlight: EdgeHighlight,
+    ) {
+        let mut edge = json! { {
+            "id": id.to_string(),
             "from": from.to_string(),
             "to": to.to_string()
         }};
 
+        if let Some(label) = label {
+            edge["label"] = json!(label.to_string());
+        }
+
         self.edges.push(edge);
     }
 }
@@ -42,13 +53,23 @@ impl JsonExporter {
     }
 
     pub fn export_json(&mut self, graph: &Graph) -> String {
-        graph.export(self);
+        // this is how the graph is saved, so nothing can be left out
+        graph.export_all(self);
 
-        let value = json! {{
+        let mut value = json! {{
         "nodes": self.nodes,
         "edges": self.edges,
-        "is_left_right": self.is_left_right
+        "is_left_right": self.is_left_right,
+        "is_strict_dag": graph.is_strict_dag(),
+        "node_high_water": graph.node_high_water(),
+        "edge_high_water": graph.edge_high_water()
         }};
+        if let Some(palette) = graph.palette() {
+            value["palette"] = json!(palette);
+        }
+        if graph.show_legend() {
+            value["show_legend"] = json!(true);
+        }
         serde_json::to_string_pretty(&value).expect("could not serialise json")
     }
 }
@@ -65,8 +86,13 @@ struct JsonNode {
 
 #[derive(Serialize, Deserialize)]
 struct JsonEdge {
+    // older files did not save edge ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Id>,
     from: Id,
     to: Id,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<Label>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -74,6 +100,17 @@ struct JsonGraph {
     nodes: Vec<JsonNode>,
     edges: Vec<JsonEdge>,
     is_left_right: bool,
+    #[serde(default)]
+    is_strict_dag: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    show_legend: bool,
+    // older files did not save high water marks; they're recalculated from the ids.
+    #[serde(default)]
+    node_high_water: usize,
+    #[serde(default)]
+    edge_high_water: usize,
 }
 
 pub fn empty_json_graph() -> String {
@@ -91,24 +128,34 @@ impl JsonImporter {
     pub fn import(&self) -> Result<Graph, anyhow::Error> {
         let value: JsonGraph = serde_json::from_str(&self.content)?;
 
-        let mut translate = HashMap::new();
         let mut graph = Graph::new();
 
         graph.set_direction(value.is_left_right);
 
         for node in &value.nodes {
-            let (new_id, _) = graph.insert_node(node.label.clone());
-            translate.insert(node.id.clone(), new_id);
+            graph.insert_node_with_id(node.id.clone(), node.label.clone())?;
         }
 
         for edge in &value.edges {
-            let new_from_id = translate.get(&edge.from);
-            let new_to_id = translate.get(&edge.to);
-            if let (Some(new_from_id), Some(new_to_id)) = (new_from_id, new_to_id) {
-                graph.link_edge(new_from_id, new_to_id);
+            if let Some(id) = &edge.id {
+                graph.link_edge_with_id(id.clone(), &edge.from, &edge.to, edge.label.clone())?;
             }
         }
 
+        // edges from older files get fresh ids, after any which were saved with one.
+        for edge in value.edges.iter().filter(|e| e.id.is_none()) {
+            graph.link_edge(&edge.from, &edge.to, edge.label.clone())?;
+        }
+
+        graph.raise_high_water_marks(value.node_high_water, value.edge_high_water);
+
+        // only once all the edges are in, since files from before it was switched on may break it.
+        graph.set_strict_dag(value.is_strict_dag);
+        if let Some(palette) = value.palette {
+            graph.set_palette(palette);
+        }
+        graph.set_show_legend(value.show_legend);
+
         Ok(graph)
     }
 }
@@ -126,6 +173,28 @@ mod tests {
         importer.import().expect("could not import");
     }
 
+    #[test]
+    fn imports_graph_saved_before_ids_were() {
+        let content = include_str!("../../test_data/imports_legacy_graph.json").to_string();
+        let mut graph = JsonImporter::new(content)
+            .import()
+            .expect("could not import");
+
+        let exported = JsonExporter::new().export_json(&graph);
+        assert!(exported.contains(r#""id": "e0""#));
+        assert!(exported.contains(r#""node_high_water": 2"#));
+        assert!(exported.contains(r#""edge_high_water": 1"#));
+
+        // new nodes and edges carry on after the ones already there
+        let (id, _) = graph.insert_node(Label::new("ghi"));
+        assert_eq!(id, Id::new("n2"));
+        graph
+            .link_edge(&Id::new("n1"), &Id::new("n2"), None)
+            .unwrap();
+        let exported = JsonExporter::new().export_json(&graph);
+        assert!(exported.contains(r#""id": "e1""#));
+    }
+
     #[test]
     fn round_trips_graph() {
         let content = include_str!("../../test_data/imports_graph.json").to_string();
@@ -143,26 +212,162 @@ mod tests {
     fn creates_empty_graph() {
         assert_eq!(
             empty_json_graph(),
-            r#"{"nodes":[],"edges":[],"is_left_right":false}"#.to_string()
+            r#"{"nodes":[],"edges":[],"is_left_right":false,"is_strict_dag":false,"node_high_water":0,"edge_high_water":0}"#
+                .to_string()
         );
     }
 
+    #[test]
+    fn preserves_ids_after_deletions() {
+        let mut graph = Graph::new();
+        for label in ["a", "b", "c", "d"] {
+            graph
+                .apply_command(GraphCommand::InsertNode {
+                    label: Label::new(label),
+                })
+                .unwrap();
+        }
+        graph
+            .apply_command(GraphCommand::LinkEdge {
+                from: Id::new("n0"),
+                to: Id::new("n3"),
+                label: None,
+            })
+            .unwrap();
+        graph
+            .apply_command(GraphCommand::LinkEdge {
+                from: Id::new("n2"),
+                to: Id::new("n3"),
+                label: None,
+            })
+            .unwrap();
+        graph
+            .apply_command(GraphCommand::DeleteNode { id: Id::new("n0") })
+            .unwrap();
+        graph
+            .apply_command(GraphCommand::DeleteNode { id: Id::new("n1") })
+            .unwrap();
+
+        let exported = JsonExporter::new().export_json(&graph);
+        let mut imported = JsonImporter::new(exported.clone())
+            .import()
+            .expect("could not import");
+
+        assert_eq!(
+            imported.find_node_label(&Id::new("n3")),
+            Some(Label::new("d"))
+        );
+        assert_eq!(JsonExporter::new().export_json(&imported), exported);
+
+        // deleted ids are not reused
+        let (id, _) = imported.insert_node(Label::new("e"));
+        assert_eq!(id, Id::new("n4"));
+    }
+
+    #[test]
+    fn round_trips_the_palette() {
+        let mut graph = Graph::new();
+        assert!(!JsonExporter::new().export_json(&graph).contains("palette"));
+
+        graph.set_palette("rainbow");
+        let exported = JsonExporter::new().export_json(&graph);
+        let imported = JsonImporter::new(exported).import().unwrap();
+        assert_eq!(imported.palette(), Some("rainbow"));
+    }
+
+    #[test]
+    fn round_trips_the_legend() {
+        let mut graph = Graph::new();
+        assert!(!JsonExporter::new().export_json(&graph).contains("legend"));
+
+        graph.set_show_legend(true);
+        let exported = JsonExporter::new().export_json(&graph);
+        let imported = JsonImporter::new(exported).import().unwrap();
+        assert!(imported.show_legend());
+    }
+
+    #[test]
+    fn round_trips_edge_labels() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("a"));
+        graph.insert_node(Label::new("b"));
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), Some(Label::new("funds")))
+            .unwrap();
+
+        let exported = JsonExporter::new().export_json(&graph);
+        assert!(exported.contains(r#""label": "funds""#));
+
+        let imported = JsonImporter::new(exported.clone())
+            .import()
+            .expect("could not import");
+        assert_eq!(JsonExporter::new().export_json(&imported), exported);
+    }
+
+    #[test]
+    fn migrates_files_without_edge_ids() {
+        let content = r#"{
+            "nodes": [{"id": "n0", "label": "a"}, {"id": "n5", "label": "b"}],
+            "edges": [{"from": "n0", "to": "n5"}, {"from": "n5", "to": "n0"}],
+            "is_left_right": false
+        }"#;
+        let mut graph = JsonImporter::new(content)
+            .import()
+            .expect("could not import");
+
+        let exported = JsonExporter::new().export_json(&graph);
+        assert!(exported.contains(r#""id": "e1""#));
+        assert!(exported.contains(r#""edge_high_water": 2"#));
+
+        let (id, _) = graph.insert_node(Label::new("c"));
+        assert_eq!(id, Id::new("n6"));
+    }
+
+    #[test]
+    fn rejects_duplicate_ids() {
+        let content = r#"{
+            "nodes": [{"id": "n0", "label": "a"}, {"id": "n0", "label": "b"}],
+            "edges": [],
+            "is_left_right": false
+        }"#;
+        let error = JsonImporter::new(content).import().err().unwrap();
+        assert_eq!(error.to_string(), "node n0 already exists");
+
+        let content = r#"{
+            "nodes": [{"id": "n0", "label": "a"}, {"id": "n1", "label": "b"}],
+            "edges": [
+                {"id": "e0", "from": "n0", "to": "n1"},
+                {"id": "e0", "from": "n1", "to": "n0"}
+            ],
+            "is_left_right": false
+        }"#;
+        let error = JsonImporter::new(content).import().err().unwrap();
+        assert_eq!(error.to_string(), "edge e0 already exists");
+    }
+
     #[test]
     fn exports_graph() {
         let mut graph = Graph::new();
 
-        graph.apply_command(GraphCommand::InsertNode {
-            label: Label::new("abc"),
-        });
+        graph
+            .apply_command(GraphCommand::InsertNode {
+                label: Label::new("abc"),
+            })
+            .unwrap();
 
-        graph.apply_command(GraphCommand::InsertNode {
-            label: Label::new("def"),
-        });
+        graph
+            .apply_command(GraphCommand::InsertNode {
+                label: Label::new("def"),
+            })
+            .unwrap();
 
-        graph.apply_command(GraphCommand::LinkEdge {
-            from: Id::new("n0"),
-            to: Id::new("n1"),
-        });
+        graph
+            .apply_command(GraphCommand::LinkEdge {
+                from: Id::new("n0"),
+                to: Id::new("n1"),
+                label: None,
+            })
+            .unwrap();
 
         let mut exporter = JsonExporter::new();
         let exported = exporter.export_json(&graph);
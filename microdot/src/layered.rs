@@ -0,0 +1,929 @@
+use crate::colors::{Color, ColorScheme};
+use crate::graphviz::{edge_highlight_color, highlight_color, wrap_label, DisplayMode};
+use microdot_core::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
+use microdot_core::graph::Graph;
+use microdot_core::hash::extract_hashtags;
+use microdot_core::{Id, Label};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// rough metrics for a 14px sans-serif font; we have no font files to measure with.
+const FONT_SIZE: f64 = 14.0;
+const ID_FONT_SIZE: f64 = 10.0;
+const CHAR_WIDTH: f64 = 7.5;
+const ID_CHAR_WIDTH: f64 = 5.5;
+const LINE_HEIGHT: f64 = 18.0;
+const PADDING: f64 = 10.0;
+const HASHTAG_ROW_HEIGHT: f64 = 20.0;
+const MIN_NODE_WIDTH: f64 = 60.0;
+const DUMMY_SIZE: f64 = 10.0;
+const NODE_SEP: f64 = 30.0;
+const RANK_SEP: f64 = 60.0;
+const MARGIN: f64 = 20.0;
+const ROLE_CORNER_RADIUS: f64 = 10.0;
+const DOUBLE_BORDER_GAP: f64 = 3.0;
+const ORDERING_SWEEPS: usize = 12;
+const POSITIONING_SWEEPS: usize = 8;
+
+/// Lays the graph out in ranks, Sugiyama-style, and draws it as svg without needing graphviz.
+pub struct LayeredExporter {
+    nodes: Vec<LayoutNode>,
+    edges: Vec<LayoutEdge>,
+    is_left_right: bool,
+    display_mode: DisplayMode,
+    /// draw root causes and terminal effects differently to the nodes between them.
+    show_roles: bool,
+    /// the graph's palette, for colouring hashtags
+    palette: Option<String>,
+}
+
+struct LayoutNode {
+    id: Id,
+    lines: Vec<String>,
+    hash_tags: Vec<(String, Color)>,
+    bgcolor: Color,
+    role: Option<NodeRole>,
+    width: f64,
+    height: f64,
+}
+
+struct LayoutEdge {
+    id: Id,
+    from: Id,
+    to: Id,
+    label: Option<String>,
+    highlight: EdgeHighlight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+/// A node in the layered graph; either a real node or a dummy standing in for part of a long edge.
+struct Vertex {
+    rank: usize,
+    // size along the rank axis and across it; these are width and height swapped for LR graphs.
+    depth: f64,
+    breadth: f64,
+    preds: Vec<usize>,
+    succs: Vec<usize>,
+}
+
+struct Layout {
+    node_centers: Vec<Point>,
+    edge_paths: Vec<Vec<Point>>,
+    width: f64,
+    height: f64,
+}
+
+impl Exporter for LayeredExporter {
+    fn set_direction(&mut self, is_left_right: bool) {
+        self.is_left_right = is_left_right;
+    }
+
+    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight, role: NodeRole) {
+        let (hash_tags, label_text) = extract_hashtags(&label.to_string());
+        let label_text = wrap_label(&label_text, self.is_left_right);
+        let lines: Vec<String> = label_text.lines().map(|l| l.to_string()).collect();
+
+        let hash_tags: Vec<_> = hash_tags
+            .iter()
+            .map(|tag| {
+                (
+                    tag.to_string(),
+                    ColorScheme::series_in(self.palette.as_deref(), tag.hash()).get_fill_color(),
+                )
+            })
+            .collect();
+
+        let id_width = match self.display_mode {
+            DisplayMode::Interactive => (id.to_string().len() + 2) as f64 * ID_CHAR_WIDTH,
+            DisplayMode::Presentation => 0.0,
+        };
+
+        let text_width = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let prefix = if i == 0 { id_width } else { 0.0 };
+                prefix + line.chars().count() as f64 * CHAR_WIDTH
+            })
+            .fold(0.0, f64::max);
+
+        let hash_tags_width: f64 = hash_tags
+            .iter()
+            .map(|(tag, _)| tag.chars().count() as f64 * CHAR_WIDTH + PADDING)
+            .sum();
+
+        let width = (text_width.max(hash_tags_width) + 2.0 * PADDING).max(MIN_NODE_WIDTH);
+        let hash_tags_height = if hash_tags.is_empty() {
+            0.0
+        } else {
+            HASHTAG_ROW_HEIGHT
+        };
+        let height = lines.len().max(1) as f64 * LINE_HEIGHT + 2.0 * PADDING + hash_tags_height;
+
+        self.nodes.push(LayoutNode {
+            id: id.clone(),
+            lines,
+            hash_tags,
+            bgcolor: highlight_color(highlight),
+            role: self.show_roles.then_some(role),
+            width,
+            height,
+        });
+    }
+
+    fn add_edge(
+        &mut self,
+        id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        highlight: EdgeHighlight,
+    ) {
+        self.edges.push(LayoutEdge {
+            id: id.clone(),
+            from: from.clone(),
+            to: to.clone(),
+            label: label.map(|l| l.to_string()),
+            highlight,
+        });
+    }
+}
+
+impl LayeredExporter {
+    pub fn new(display_mode: DisplayMode) -> Self {
+        Self {
+            nodes: vec![],
+            edges: vec![],
+            is_left_right: false,
+            display_mode,
+            show_roles: false,
+            palette: None,
+        }
+    }
+
+    /// roots get rounded corners, leaves a double border, and unlinked nodes both.
+    pub fn show_roles(mut self, show_roles: bool) -> Self {
+        self.show_roles = show_roles;
+        self
+    }
+
+    pub fn export_svg(&mut self, graph: &Graph) -> String {
+        self.palette = graph.palette().map(String::from);
+        graph.export(self);
+        let layout = self.layout();
+        self.render(&layout)
+    }
+
+    fn node_index(&self) -> HashMap<&Id, usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (&node.id, idx))
+            .collect()
+    }
+
+    fn layout(&self) -> Layout {
+        let index = self.node_index();
+        let node_count = self.nodes.len();
+
+        let edges: Vec<Option<(usize, usize)>> = self
+            .edges
+            .iter()
+            .map(|e| match (index.get(&e.from), index.get(&e.to)) {
+                (Some(&from), Some(&to)) => Some((from, to)),
+                _ => None,
+            })
+            .collect();
+
+        // self-loops take no part in the layout; they're drawn beside their node.
+        let reversed = find_back_edges(node_count, &edges);
+        let acyclic: Vec<Option<(usize, usize)>> = edges
+            .iter()
+            .zip(&reversed)
+            .map(|(edge, &is_reversed)| match *edge {
+                Some((from, to)) if from == to => None,
+                Some((from, to)) if is_reversed => Some((to, from)),
+                edge => edge,
+            })
+            .collect();
+
+        let ranks = assign_ranks(node_count, &acyclic);
+
+        // build the layered graph, replacing long edges with chains of dummy vertices.
+        let mut vertices: Vec<Vertex> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| {
+                let (depth, breadth) = if self.is_left_right {
+                    (node.width, node.height)
+                } else {
+                    (node.height, node.width)
+                };
+                Vertex {
+                    rank: ranks[idx],
+                    depth,
+                    breadth,
+                    preds: vec![],
+                    succs: vec![],
+                }
+            })
+            .collect();
+
+        let mut chains: Vec<Vec<usize>> = vec![];
+        for edge in &acyclic {
+            let mut chain = vec![];
+            if let Some((from, to)) = *edge {
+                chain.push(from);
+                for rank in ranks[from] + 1..ranks[to] {
+                    vertices.push(Vertex {
+                        rank,
+                        depth: 0.0,
+                        breadth: DUMMY_SIZE,
+                        preds: vec![],
+                        succs: vec![],
+                    });
+                    chain.push(vertices.len() - 1);
+                }
+                chain.push(to);
+                for pair in chain.windows(2) {
+                    vertices[pair[0]].succs.push(pair[1]);
+                    vertices[pair[1]].preds.push(pair[0]);
+                }
+            }
+            chains.push(chain);
+        }
+
+        let rank_count = vertices.iter().map(|v| v.rank + 1).max().unwrap_or(0);
+        let mut layers: Vec<Vec<usize>> = vec![vec![]; rank_count];
+        for (idx, vertex) in vertices.iter().enumerate() {
+            layers[vertex.rank].push(idx);
+        }
+
+        order_layers(&mut layers, &vertices);
+        let across = position_across(&layers, &vertices);
+
+        // position along the rank axis; each rank is as deep as its deepest node.
+        let mut rank_centers = vec![];
+        let mut offset = MARGIN;
+        for layer in &layers {
+            let depth = layer.iter().map(|&v| vertices[v].depth).fold(0.0, f64::max);
+            rank_centers.push(offset + depth / 2.0);
+            offset += depth + RANK_SEP;
+        }
+        let total_depth = offset - RANK_SEP + MARGIN;
+        let total_breadth = layers
+            .iter()
+            .flat_map(|layer| layer.iter())
+            .map(|&v| across[v] + vertices[v].breadth / 2.0)
+            .fold(0.0, f64::max)
+            + MARGIN;
+
+        let point = |along: f64, across: f64| {
+            if self.is_left_right {
+                Point {
+                    x: along,
+                    y: across,
+                }
+            } else {
+                Point {
+                    x: across,
+                    y: along,
+                }
+            }
+        };
+
+        let centers: Vec<Point> = vertices
+            .iter()
+            .enumerate()
+            .map(|(idx, v)| point(rank_centers[v.rank], across[idx]))
+            .collect();
+
+        let edge_paths = chains
+            .iter()
+            .zip(&reversed)
+            .enumerate()
+            .map(|(edge_idx, (chain, &is_reversed))| {
+                if chain.is_empty() {
+                    return match edges[edge_idx] {
+                        Some((from, _)) => {
+                            self_loop(centers[from], &vertices[from], self.is_left_right)
+                        }
+                        None => vec![],
+                    };
+                }
+
+                let first = chain[0];
+                let last = chain[chain.len() - 1];
+                let mut path = vec![point(
+                    rank_centers[vertices[first].rank] + vertices[first].depth / 2.0,
+                    across[first],
+                )];
+                path.extend(chain[1..chain.len() - 1].iter().map(|&v| centers[v]));
+                path.push(point(
+                    rank_centers[vertices[last].rank] - vertices[last].depth / 2.0,
+                    across[last],
+                ));
+
+                // reversed edges were laid out backwards; point them the right way again.
+                if is_reversed {
+                    path.reverse();
+                }
+                path
+            })
+            .collect();
+
+        let (width, height) = if self.is_left_right {
+            (total_depth, total_breadth)
+        } else {
+            (total_breadth, total_depth)
+        };
+
+        Layout {
+            node_centers: centers[..node_count].to_vec(),
+            edge_paths,
+            width: width.max(2.0 * MARGIN),
+            height: height.max(2.0 * MARGIN),
+        }
+    }
+
+    fn render(&self, layout: &Layout) -> String {
+        let stroke = ColorScheme::normal().get_stroke_color();
+        let mut svg = String::new();
+
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}" font-family="helvetica, sans-serif" font-size="{f}">"#,
+            w = layout.width,
+            h = layout.height,
+            f = FONT_SIZE
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"  <defs><marker id="arrowhead" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="4" markerHeight="4" orient="auto"><path d="M 0 0 L 10 5 L 0 10 z" fill="{}"/></marker></defs>"#,
+            stroke
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"  <rect width="100%" height="100%" fill="{}"/>"#,
+            ColorScheme::normal().get_fill_color()
+        )
+        .unwrap();
+
+        for (edge, path) in self.edges.iter().zip(&layout.edge_paths) {
+            if path.len() < 2 {
+                continue;
+            }
+            let width = match edge.highlight {
+                EdgeHighlight::Normal => 2,
+                EdgeHighlight::OnPath => 4,
+            };
+            writeln!(
+                svg,
+                r#"  <path d="{}" fill="none" stroke="{}" stroke-width="{}" marker-end="url(#arrowhead)"/>"#,
+                self.path_data(path),
+                edge_highlight_color(edge.highlight),
+                width
+            )
+            .unwrap();
+
+            let text = match (self.display_mode, &edge.label) {
+                (DisplayMode::Interactive, Some(label)) => Some(format!("{}: {}", edge.id, label)),
+                (DisplayMode::Interactive, None) => Some(edge.id.to_string()),
+                (DisplayMode::Presentation, Some(label)) => Some(label.clone()),
+                (DisplayMode::Presentation, None) => None,
+            };
+
+            if let Some(text) = text {
+                let mid = path.len() / 2;
+                let at = midpoint(path[mid - 1], path[mid]);
+                writeln!(
+                    svg,
+                    r#"  <text x="{:.1}" y="{:.1}" font-size="{}">{}</text>"#,
+                    at.x + 4.0,
+                    at.y - 4.0,
+                    ID_FONT_SIZE + 2.0,
+                    escape_xml(&text)
+                )
+                .unwrap();
+            }
+        }
+
+        for (node, center) in self.nodes.iter().zip(&layout.node_centers) {
+            self.render_node(&mut svg, node, *center, stroke);
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn render_node(&self, svg: &mut String, node: &LayoutNode, center: Point, stroke: Color) {
+        let left = center.x - node.width / 2.0;
+        let top = center.y - node.height / 2.0;
+
+        let (is_rounded, is_double) = match node.role {
+            Some(NodeRole::Root) => (true, false),
+            Some(NodeRole::Leaf) => (false, true),
+            Some(NodeRole::Isolated) => (true, true),
+            Some(NodeRole::Intermediate) | None => (false, false),
+        };
+        let corner = if is_rounded { ROLE_CORNER_RADIUS } else { 0.0 };
+
+        writeln!(svg, r#"  <g id="{}">"#, escape_xml(&node.id.to_string())).unwrap();
+        writeln!(
+            svg,
+            r#"    <rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" rx="{:.1}" fill="{}" stroke="{}"/>"#,
+            left, top, node.width, node.height, corner, node.bgcolor, stroke
+        )
+        .unwrap();
+        if is_double {
+            writeln!(
+                svg,
+                r#"    <rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" rx="{:.1}" fill="none" stroke="{}"/>"#,
+                left - DOUBLE_BORDER_GAP,
+                top - DOUBLE_BORDER_GAP,
+                node.width + 2.0 * DOUBLE_BORDER_GAP,
+                node.height + 2.0 * DOUBLE_BORDER_GAP,
+                corner,
+                stroke
+            )
+            .unwrap();
+        }
+
+        let lines = if node.lines.is_empty() {
+            vec![String::new()]
+        } else {
+            node.lines.clone()
+        };
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + PADDING + (i as f64 + 0.75) * LINE_HEIGHT;
+            let id_prefix = match (i, self.display_mode) {
+                (0, DisplayMode::Interactive) => format!(
+                    r#"<tspan font-size="{}">{}</tspan>: "#,
+                    ID_FONT_SIZE,
+                    escape_xml(&node.id.to_string())
+                ),
+                _ => String::new(),
+            };
+            writeln!(
+                svg,
+                r#"    <text x="{:.1}" y="{:.1}" text-anchor="middle" fill="{}">{}{}</text>"#,
+                center.x,
+                y,
+                node.bgcolor.text_color(),
+                id_prefix,
+                escape_xml(line)
+            )
+            .unwrap();
+        }
+
+        if !node.hash_tags.is_empty() {
+            let cell_width = (node.width - 2.0) / node.hash_tags.len() as f64;
+            let cell_top = top + node.height - HASHTAG_ROW_HEIGHT - 1.0;
+            for (i, (tag, color)) in node.hash_tags.iter().enumerate() {
+                let cell_left = left + 1.0 + i as f64 * cell_width;
+                writeln!(
+                    svg,
+                    r#"    <rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    cell_left, cell_top, cell_width, HASHTAG_ROW_HEIGHT, color
+                )
+                .unwrap();
+                writeln!(
+                    svg,
+                    r#"    <text x="{:.1}" y="{:.1}" text-anchor="middle" fill="{}">{}</text>"#,
+                    cell_left + cell_width / 2.0,
+                    cell_top + HASHTAG_ROW_HEIGHT * 0.75,
+                    color.text_color(),
+                    escape_xml(tag)
+                )
+                .unwrap();
+            }
+        }
+
+        svg.push_str("  </g>\n");
+    }
+
+    /// a smooth path through the points, leaving and entering each along the rank axis.
+    fn path_data(&self, path: &[Point]) -> String {
+        let mut d = format!("M {:.1} {:.1}", path[0].x, path[0].y);
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let (c1, c2) = if self.is_left_right {
+                let mid = (a.x + b.x) / 2.0;
+                (Point { x: mid, y: a.y }, Point { x: mid, y: b.y })
+            } else {
+                let mid = (a.y + b.y) / 2.0;
+                (Point { x: a.x, y: mid }, Point { x: b.x, y: mid })
+            };
+            write!(
+                d,
+                " C {:.1} {:.1} {:.1} {:.1} {:.1} {:.1}",
+                c1.x, c1.y, c2.x, c2.y, b.x, b.y
+            )
+            .unwrap();
+        }
+        d
+    }
+}
+
+/// Finds a set of edges which, if reversed, leave the graph without cycles.
+fn find_back_edges(node_count: usize, edges: &[Option<(usize, usize)>]) -> Vec<bool> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Unvisited,
+        OnStack,
+        Done,
+    }
+
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; node_count];
+    for (edge_idx, edge) in edges.iter().enumerate() {
+        if let Some((from, to)) = *edge {
+            if from != to {
+                outgoing[from].push(edge_idx);
+            }
+        }
+    }
+
+    let mut reversed = vec![false; edges.len()];
+    let mut state = vec![State::Unvisited; node_count];
+
+    for start in 0..node_count {
+        if state[start] != State::Unvisited {
+            continue;
+        }
+
+        // iterative dfs; each frame is a node and how far through its edges we are.
+        let mut stack = vec![(start, 0)];
+        state[start] = State::OnStack;
+
+        while let Some((node, next)) = stack.pop() {
+            if next < outgoing[node].len() {
+                stack.push((node, next + 1));
+                let edge_idx = outgoing[node][next];
+                let (_, to) = edges[edge_idx].expect("only known edges are followed");
+                match state[to] {
+                    State::Unvisited => {
+                        state[to] = State::OnStack;
+                        stack.push((to, 0));
+                    }
+                    State::OnStack => reversed[edge_idx] = true,
+                    State::Done => {}
+                }
+            } else {
+                state[node] = State::Done;
+            }
+        }
+    }
+
+    reversed
+}
+
+/// Longest-path ranking, then sources are pulled down to sit just above their first successor.
+fn assign_ranks(node_count: usize, edges: &[Option<(usize, usize)>]) -> Vec<usize> {
+    let mut in_degree = vec![0; node_count];
+    let mut outgoing: Vec<Vec<usize>> = vec![vec![]; node_count];
+    for &(from, to) in edges.iter().flatten() {
+        in_degree[to] += 1;
+        outgoing[from].push(to);
+    }
+
+    let mut ranks = vec![0; node_count];
+    let mut ready: Vec<usize> = (0..node_count).filter(|&n| in_degree[n] == 0).collect();
+    ready.reverse();
+    let mut topological = vec![];
+
+    while let Some(node) = ready.pop() {
+        topological.push(node);
+        for &succ in &outgoing[node] {
+            ranks[succ] = ranks[succ].max(ranks[node] + 1);
+            in_degree[succ] -= 1;
+            if in_degree[succ] == 0 {
+                ready.push(succ);
+            }
+        }
+    }
+
+    let mut has_preds = vec![false; node_count];
+    for &(_, to) in edges.iter().flatten() {
+        has_preds[to] = true;
+    }
+
+    for &node in topological.iter().rev() {
+        if !has_preds[node] {
+            if let Some(min_succ) = outgoing[node].iter().map(|&s| ranks[s]).min() {
+                ranks[node] = min_succ - 1;
+            }
+        }
+    }
+
+    ranks
+}
+
+/// Reorders each layer to reduce edge crossings, using the barycenter heuristic.
+fn order_layers(layers: &mut [Vec<usize>], vertices: &[Vertex]) {
+    let mut best = layers.to_vec();
+    let mut best_crossings = count_crossings(layers, vertices);
+
+    for sweep in 0..ORDERING_SWEEPS {
+        let downwards = sweep % 2 == 0;
+        let ranks: Vec<usize> = if downwards {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+
+        for rank in ranks {
+            let fixed = if downwards { rank - 1 } else { rank + 1 };
+            let positions: HashMap<usize, usize> = layers[fixed]
+                .iter()
+                .enumerate()
+                .map(|(pos, &v)| (v, pos))
+                .collect();
+
+            let mut keyed: Vec<(f64, usize)> = layers[rank]
+                .iter()
+                .enumerate()
+                .map(|(pos, &v)| {
+                    let neighbours = if downwards {
+                        &vertices[v].preds
+                    } else {
+                        &vertices[v].succs
+                    };
+                    let key = if neighbours.is_empty() {
+                        pos as f64
+                    } else {
+                        neighbours.iter().map(|n| positions[n] as f64).sum::<f64>()
+                            / neighbours.len() as f64
+                    };
+                    (key, v)
+                })
+                .collect();
+
+            keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            layers[rank] = keyed.into_iter().map(|(_, v)| v).collect();
+        }
+
+        let crossings = count_crossings(layers, vertices);
+        if crossings < best_crossings {
+            best_crossings = crossings;
+            best = layers.to_vec();
+        }
+    }
+
+    layers.clone_from_slice(&best);
+}
+
+fn count_crossings(layers: &[Vec<usize>], vertices: &[Vertex]) -> usize {
+    let mut crossings = 0;
+
+    for pair in layers.windows(2) {
+        let lower_positions: HashMap<usize, usize> = pair[1]
+            .iter()
+            .enumerate()
+            .map(|(pos, &v)| (v, pos))
+            .collect();
+
+        let segments: Vec<(usize, usize)> = pair[0]
+            .iter()
+            .enumerate()
+            .flat_map(|(pos, &v)| {
+                vertices[v]
+                    .succs
+                    .iter()
+                    .filter_map(|s| lower_positions.get(s))
+                    .map(move |&s| (pos, s))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        for (i, a) in segments.iter().enumerate() {
+            for b in &segments[i + 1..] {
+                if (a.0 < b.0 && a.1 > b.1) || (a.0 > b.0 && a.1 < b.1) {
+                    crossings += 1;
+                }
+            }
+        }
+    }
+
+    crossings
+}
+
+/// Works out where each vertex sits across the rank axis, pulling each towards the average of its
+/// neighbours while keeping the order within its layer and a minimum gap between vertices.
+fn position_across(layers: &[Vec<usize>], vertices: &[Vertex]) -> Vec<f64> {
+    let mut across = vec![0.0; vertices.len()];
+
+    for layer in layers {
+        let mut offset = MARGIN;
+        for &v in layer {
+            across[v] = offset + vertices[v].breadth / 2.0;
+            offset += vertices[v].breadth + NODE_SEP;
+        }
+    }
+
+    for sweep in 0..POSITIONING_SWEEPS {
+        let downwards = sweep % 2 == 0;
+        let ranks: Vec<usize> = if downwards {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+
+        for rank in ranks {
+            let layer = &layers[rank];
+            let desired: Vec<f64> = layer
+                .iter()
+                .map(|&v| {
+                    let neighbours = if downwards {
+                        &vertices[v].preds
+                    } else {
+                        &vertices[v].succs
+                    };
+                    if neighbours.is_empty() {
+                        across[v]
+                    } else {
+                        neighbours.iter().map(|&n| across[n]).sum::<f64>() / neighbours.len() as f64
+                    }
+                })
+                .collect();
+
+            let gap = |i: usize| {
+                (vertices[layer[i]].breadth + vertices[layer[i + 1]].breadth) / 2.0 + NODE_SEP
+            };
+
+            // pack from the left, then from the right; both keep the gaps, and so does their
+            // average, which doesn't favour either side.
+            let mut from_left = desired.clone();
+            for i in 1..layer.len() {
+                from_left[i] = from_left[i].max(from_left[i - 1] + gap(i - 1));
+            }
+            let mut from_right = desired.clone();
+            for i in (0..layer.len().saturating_sub(1)).rev() {
+                from_right[i] = from_right[i].min(from_right[i + 1] - gap(i));
+            }
+
+            for (i, &v) in layer.iter().enumerate() {
+                across[v] = (from_left[i] + from_right[i]) / 2.0;
+            }
+        }
+    }
+
+    // shift everything so the leftmost edge sits on the margin.
+    let min = vertices
+        .iter()
+        .enumerate()
+        .map(|(v, vertex)| across[v] - vertex.breadth / 2.0)
+        .fold(f64::INFINITY, f64::min);
+    if min.is_finite() {
+        for value in across.iter_mut() {
+            *value += MARGIN - min;
+        }
+    }
+
+    across
+}
+
+fn self_loop(center: Point, vertex: &Vertex, is_left_right: bool) -> Vec<Point> {
+    let (half_across, reach) = (vertex.breadth / 2.0, 20.0);
+    if is_left_right {
+        let top = center.y - half_across;
+        vec![
+            Point {
+                x: center.x - 10.0,
+                y: top,
+            },
+            Point {
+                x: center.x,
+                y: top - reach,
+            },
+            Point {
+                x: center.x + 10.0,
+                y: top,
+            },
+        ]
+    } else {
+        let right = center.x + half_across;
+        vec![
+            Point {
+                x: right,
+                y: center.y - 10.0,
+            },
+            Point {
+                x: right + reach,
+                y: center.y,
+            },
+            Point {
+                x: right,
+                y: center.y + 10.0,
+            },
+        ]
+    }
+}
+
+fn midpoint(a: Point, b: Point) -> Point {
+    Point {
+        x: (a.x + b.x) / 2.0,
+        y: (a.y + b.y) / 2.0,
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdot_core::command::GraphCommand;
+
+    fn graph_from(labels: &[&str], links: &[(&str, &str)]) -> Graph {
+        let mut graph = Graph::new();
+        for label in labels {
+            graph.insert_node(Label::new(*label));
+        }
+        for (from, to) in links {
+            graph
+                .link_edge(&Id::new(*from), &Id::new(*to), None)
+                .unwrap();
+        }
+        graph
+    }
+
+    #[test]
+    fn ranks_follow_edges() {
+        let edges = vec![Some((0, 1)), Some((1, 2)), Some((0, 2)), Some((3, 2))];
+        let ranks = assign_ranks(4, &edges);
+        assert_eq!(ranks, vec![0, 1, 2, 1]);
+    }
+
+    #[test]
+    fn reverses_enough_edges_to_break_cycles() {
+        let edges = vec![Some((0, 1)), Some((1, 2)), Some((2, 0)), None];
+        let reversed = find_back_edges(3, &edges);
+        assert_eq!(reversed, vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn ordering_removes_avoidable_crossings() {
+        // n0 -> n3 and n1 -> n2 cross if the second layer keeps its insertion order.
+        let graph = graph_from(&["a", "b", "c", "d"], &[("n0", "n3"), ("n1", "n2")]);
+        let mut exporter = LayeredExporter::new(DisplayMode::Interactive);
+        graph.export(&mut exporter);
+        let layout = exporter.layout();
+
+        let centers = &layout.node_centers;
+        assert_eq!(centers[0].x < centers[1].x, centers[3].x < centers[2].x);
+    }
+
+    #[test]
+    fn renders_every_node_and_edge() {
+        let mut graph = graph_from(
+            &["first #tag", "second", "third & <last>"],
+            &[("n0", "n1"), ("n1", "n2"), ("n2", "n0"), ("n1", "n1")],
+        );
+        graph
+            .apply_command(GraphCommand::LabelEdge {
+                id: Id::new("e0"),
+                label: Some(Label::new("causes")),
+            })
+            .unwrap();
+
+        let svg = LayeredExporter::new(DisplayMode::Interactive).export_svg(&graph);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<g id=").count(), 3);
+        assert_eq!(svg.matches("marker-end").count(), 4);
+        assert!(svg.contains("e0: causes"));
+        assert!(svg.contains("#tag"));
+        assert!(svg.contains("third &amp; &lt;last&gt;"));
+    }
+
+    #[test]
+    fn lays_out_left_to_right() {
+        let mut graph = graph_from(&["a", "b"], &[("n0", "n1")]);
+        graph.set_direction(true);
+        let mut exporter = LayeredExporter::new(DisplayMode::Presentation);
+        graph.export(&mut exporter);
+        let layout = exporter.layout();
+
+        let centers = &layout.node_centers;
+        assert!(centers[0].x < centers[1].x);
+        assert_eq!(centers[0].y, centers[1].y);
+    }
+
+    #[test]
+    fn lays_out_an_empty_graph() {
+        let svg = LayeredExporter::new(DisplayMode::Interactive).export_svg(&Graph::new());
+        assert!(svg.contains("</svg>"));
+    }
+}
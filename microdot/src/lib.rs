@@ -1,16 +1,23 @@
 use microdot_core::command::GraphCommand;
+use microdot_core::graph::{Filter, Selection};
 use microdot_core::{Id, Label, Line};
 use rustyline::{Editor, Helper};
+use std::path::PathBuf;
 
 pub mod colors;
+pub mod dot;
 mod fdg;
 pub mod graphviz;
 pub mod helper;
 pub mod json;
+pub mod layered;
+pub mod mermaid;
 pub mod palettes;
 pub mod parser;
+pub mod preview;
 pub mod repl;
 mod storage;
+pub mod styles;
 pub mod svg;
 pub mod util;
 
@@ -19,8 +26,22 @@ pub enum Command {
     GraphCommand(GraphCommand),
     ShowHelp,
     Search { sub_label: Label },
+    Upstream { id: Id },
+    Downstream { id: Id },
+    ShortestPath { from: Id, to: Id },
+    AllPaths { from: Id, to: Id },
+    ListCycles,
+    ListRoots,
+    ListLeaves,
     PrintDot,
     PrintJson,
+    PrintMermaid,
+    Import { path: PathBuf },
+    Merge { path: PathBuf, unify_labels: bool },
+    Extract { selection: Selection, path: PathBuf },
+    Cluster { tags: Vec<String> },
+    Filter { filter: Option<Filter> },
+    Palette { name: Option<String> },
     RenameNodeUnlabelled { id: Id },
     Save,
     Show,
@@ -37,13 +58,104 @@ impl Command {
             Command::Search { sub_label } => {
                 format!("search for <{}> and highlight matching nodes", sub_label)
             }
+            Command::Upstream { id } => {
+                format!(
+                    "list and highlight everything upstream of <{}>, by depth",
+                    id
+                )
+            }
+            Command::Downstream { id } => {
+                format!(
+                    "list and highlight everything downstream of <{}>, by depth",
+                    id
+                )
+            }
+            Command::ShortestPath { from, to } => {
+                format!(
+                    "highlight and print the shortest path from <{}> to <{}>",
+                    from, to
+                )
+            }
+            Command::AllPaths { from, to } => {
+                format!("highlight and print every path from <{}> to <{}>", from, to)
+            }
+            Command::ListRoots => "list the root causes: nodes with nothing linking to them".into(),
+            Command::ListLeaves => "list the end effects: nodes which link to nothing".into(),
+            Command::ListCycles => "list every cycle in the graph by node id".into(),
             Command::PrintDot => "print the dot definition for this graph to the terminal".into(),
             Command::PrintJson => "print the json definition for this graph to the terminal".into(),
+            Command::PrintMermaid => {
+                "print the mermaid flowchart for this graph to the terminal".into()
+            }
+            Command::Import { path } => {
+                format!(
+                    "import the graph in <{}>, replacing the current one",
+                    path.to_string_lossy()
+                )
+            }
+            Command::Merge { path, unify_labels } => {
+                let path = path.to_string_lossy();
+                if *unify_labels {
+                    format!(
+                        "merge the graph in <{}> into this one, reusing nodes with the same label",
+                        path
+                    )
+                } else {
+                    format!(
+                        "merge the graph in <{}> into this one, giving its nodes fresh ids",
+                        path
+                    )
+                }
+            }
+            Command::Extract { selection, path } => {
+                let path = path.to_string_lossy();
+                match selection {
+                    Selection::Nodes(ids) => {
+                        let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+                        format!(
+                            "write the nodes <{}> and the edges between them to <{}>",
+                            ids.join(" "),
+                            path
+                        )
+                    }
+                    Selection::HashTag(tag) => format!(
+                        "write the nodes tagged <#{}> and the edges between them to <{}>",
+                        tag, path
+                    ),
+                    Selection::Reach(id, reach) => format!(
+                        "write <{}>, everything {} of it and the edges between them to <{}>",
+                        id, reach, path
+                    ),
+                }
+            }
+            Command::Cluster { tags } => {
+                if tags.is_empty() {
+                    "stop grouping tagged nodes into boxes".into()
+                } else {
+                    let tags: Vec<_> = tags.iter().map(|tag| format!("#{}", tag)).collect();
+                    format!(
+                        "group the nodes tagged <{}> into boxes of their colour",
+                        tags.join(" ")
+                    )
+                }
+            }
+            Command::Filter { filter } => match filter {
+                Some(filter) if filter.with_neighbours => format!(
+                    "show only the nodes tagged <{}>, and the nodes linked to them",
+                    filter.expr
+                ),
+                Some(filter) => format!("show only the nodes tagged <{}>", filter.expr),
+                None => "show every node again".into(),
+            },
+            Command::Palette { name } => match name {
+                Some(name) => format!("colour hashtags from the <{}> palette", name),
+                None => "list the palettes, marking the one in use".into(),
+            },
             Command::RenameNodeUnlabelled { id } => {
                 format!("rename <{}> but no new label text supplied", id)
             }
             Command::Save => "save the graph to disc".into(),
-            Command::Show => "open the diagram in Gapplin".into(),
+            Command::Show => "open the diagram in Gapplin, or show the preview address".into(),
             Command::Exit => "exit microdot".into(),
             Command::ParseError { line } => format!("could not parse: \"{}\"", line),
         }
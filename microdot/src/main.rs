@@ -1,12 +1,17 @@
-use clap::{Parser, ValueHint};
+use anyhow::anyhow;
+use clap::{Parser, Subcommand, ValueHint};
 use libmicrodot::helper::{GetNodeLabel, MicrodotHelper};
-use libmicrodot::json::{empty_json_graph, JsonImporter};
-use libmicrodot::repl::repl;
+use libmicrodot::json::{empty_json_graph, JsonExporter, JsonImporter};
+use libmicrodot::palettes::{load_user_palettes, user_palettes_dir};
+use libmicrodot::preview::PreviewServer;
+use libmicrodot::repl::{import_file, repl, undo_file, RenderMethod, ReplOptions};
+use libmicrodot::styles::StyleRules;
+use libmicrodot::util::ScriptInteraction;
 use microdot_core::graph::*;
 use microdot_core::*;
 use rustyline::{Config, Editor};
 use std::fs::File;
-use std::io::Read;
+use std::io::{IsTerminal, Read};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
@@ -21,6 +26,57 @@ struct Opts {
     /// Sets a custom config file. Could have been an Option<T> with no default too
     #[clap(short, long, value_hint = ValueHint::FilePath)]
     history: Option<PathBuf>,
+
+    /// Keep the undo history in a file next to the graph, so undo works after a restart
+    #[clap(long)]
+    persist_undo: bool,
+
+    /// How to draw the graph; defaults to graphviz if it's installed, or the built-in layout if not
+    #[clap(short, long, value_enum)]
+    render: Option<RenderMethod>,
+
+    /// Also save the graph as a mermaid flowchart, in a .mmd file next to the graph file
+    #[clap(long)]
+    mermaid: bool,
+
+    /// Serve a live-reloading preview of the graph on this local port
+    #[clap(short, long)]
+    serve: Option<u16>,
+
+    /// Run the commands in this file instead of prompting for them; commands are also read from
+    /// stdin when it isn't a terminal. Exits with an error if any command fails
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    script: Option<PathBuf>,
+
+    /// Apply commands to the graph in memory only, without saving or rendering anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Stop running a script at the first command which fails
+    #[clap(long)]
+    stop_on_error: bool,
+
+    /// Draw root causes with rounded corners and end effects with a double border
+    #[clap(long)]
+    show_roles: bool,
+
+    /// Read extra palettes from this directory, rather than the palettes directory in the
+    /// microdot config directory
+    #[clap(long, value_hint = ValueHint::DirPath)]
+    palettes: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Convert a graphviz dot (or mermaid) file into a microdot graph, saved to --file or next to
+    /// the original with a .json extension
+    Import {
+        #[clap(value_hint = ValueHint::FilePath)]
+        source: PathBuf,
+    },
 }
 
 impl Opts {
@@ -30,6 +86,10 @@ impl Opts {
             .unwrap_or_else(|| dirs::home_dir().unwrap().join(".microdot_history"))
     }
 
+    fn palettes(&self) -> Option<PathBuf> {
+        self.palettes.clone().or_else(user_palettes_dir)
+    }
+
     fn file(&self) -> PathBuf {
         self.file
             .clone()
@@ -50,11 +110,73 @@ impl GetNodeLabel for GraphGetNodeLabel {
 
 fn main() -> Result<(), anyhow::Error> {
     let opts = Opts::parse();
+
+    if let Some(Action::Import { source }) = &opts.action {
+        let json_file = opts
+            .file
+            .clone()
+            .unwrap_or_else(|| source.with_extension("json"));
+        return import(source, &json_file);
+    }
+
     let history = opts.history();
     let json_file = opts.file();
 
-    let graph = load_graph(&json_file)?;
+    if let Some(palettes) = opts.palettes() {
+        load_user_palettes(&palettes)?;
+    }
+
+    let mut graph = load_graph(&json_file)?;
+
+    let undo_file = undo_file(&json_file);
+    if opts.persist_undo && undo_file.exists() {
+        let history = std::fs::read_to_string(&undo_file)?;
+        graph.import_history(&history)?;
+    }
+
     let graph = Arc::new(RwLock::new(graph));
+
+    let preview = match opts.serve {
+        Some(port) => {
+            let server = PreviewServer::start(port)?;
+            println!("serving preview at {}", server.url());
+            Some(server)
+        }
+        None => None,
+    };
+
+    let options = ReplOptions {
+        persist_undo: opts.persist_undo,
+        render_method: opts.render.unwrap_or_else(RenderMethod::detect),
+        save_mermaid: opts.mermaid,
+        preview,
+        dry_run: opts.dry_run,
+        stop_on_error: opts.stop_on_error,
+        show_roles: opts.show_roles,
+        styles: StyleRules::load(&json_file)?,
+    };
+
+    // commands from a file, or piped in, run without the line editor.
+    let script = match &opts.script {
+        Some(script_file) => Some(std::fs::read_to_string(script_file)?),
+        None if !std::io::stdin().is_terminal() => {
+            let mut script = String::new();
+            std::io::stdin().read_to_string(&mut script)?;
+            Some(script)
+        }
+        None => None,
+    };
+
+    if let Some(script) = script {
+        let mut interaction = ScriptInteraction::new(&script);
+        let failures = repl(&mut interaction, &json_file, graph, &options)?;
+        if failures > 0 {
+            eprintln!("{} command(s) failed", failures);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let gnl = GraphGetNodeLabel {
         graph: graph.clone(),
     };
@@ -73,13 +195,34 @@ fn main() -> Result<(), anyhow::Error> {
         );
     }
 
-    repl(&mut rl, &json_file, graph)?;
+    repl(&mut rl, &json_file, graph, &options)?;
 
     rl.save_history(&history).unwrap();
 
     Ok(())
 }
 
+fn import(source: &Path, json_file: &Path) -> Result<(), anyhow::Error> {
+    if json_file.exists() {
+        return Err(anyhow!(
+            "{} already exists; choose another with --file",
+            json_file.to_string_lossy()
+        ));
+    }
+
+    let graph = import_file(source)?;
+    let json = JsonExporter::new().export_json(&graph);
+    std::fs::write(json_file, json)?;
+
+    println!(
+        "imported {} into {}",
+        source.to_string_lossy(),
+        json_file.to_string_lossy()
+    );
+
+    Ok(())
+}
+
 fn load_graph(json_file: &Path) -> Result<Graph, anyhow::Error> {
     let json_content = if json_file.exists() {
         println!(
@@ -0,0 +1,429 @@
+use crate::colors::{Color, ColorScheme};
+use crate::graphviz::{edge_highlight_color, highlight_color};
+use anyhow::anyhow;
+use microdot_core::exporter::{EdgeHighlight, Exporter, NodeHighlight, NodeRole};
+use microdot_core::graph::Graph;
+use microdot_core::hash::extract_hashtags;
+use microdot_core::{Id, Label};
+use pom::parser::*;
+use std::collections::BTreeMap;
+
+/// Writes a graph as a mermaid flowchart, so it can be pasted into markdown which renders
+/// mermaid natively. Hashtags become classes, and search and query results are styled.
+#[derive(Default)]
+pub struct MermaidExporter {
+    is_left_right: bool,
+    nodes: Vec<String>,
+    edges: Vec<String>,
+    classes: BTreeMap<String, (Color, Vec<String>)>,
+    styles: Vec<String>,
+    /// the graph's palette, for colouring hashtags
+    palette: Option<String>,
+    /// leave out nothing, whatever the graph's filter, as when saving it.
+    whole_graph: bool,
+}
+
+impl Exporter for MermaidExporter {
+    fn set_direction(&mut self, is_left_right: bool) {
+        self.is_left_right = is_left_right;
+    }
+
+    fn add_node(&mut self, id: &Id, label: &Label, highlight: NodeHighlight, _role: NodeRole) {
+        let label = label.to_string();
+        self.nodes
+            .push(format!("    {}[\"{}\"]", id, escape_text(&label)));
+
+        let (hash_tags, _) = extract_hashtags(&label);
+        for tag in hash_tags {
+            let fill = ColorScheme::series_in(self.palette.as_deref(), tag.hash()).get_fill_color();
+            let (_, members) = self
+                .classes
+                .entry(class_name(&tag.to_string()))
+                .or_insert_with(|| (fill, vec![]));
+            members.push(id.to_string());
+        }
+
+        if let NodeHighlight::SearchResult | NodeHighlight::Reachable | NodeHighlight::OnPath =
+            highlight
+        {
+            let fill = highlight_color(highlight);
+            self.styles.push(format!(
+                "    style {} fill:{},color:{}",
+                id,
+                fill,
+                fill.text_color()
+            ));
+        }
+    }
+
+    fn add_edge(
+        &mut self,
+        _id: &Id,
+        from: &Id,
+        to: &Id,
+        label: Option<&Label>,
+        highlight: EdgeHighlight,
+    ) {
+        // mermaid styles links by their position in the chart
+        if let EdgeHighlight::OnPath = highlight {
+            self.styles.push(format!(
+                "    linkStyle {} stroke:{},stroke-width:4px",
+                self.edges.len(),
+                edge_highlight_color(highlight)
+            ));
+        }
+
+        let line = match label {
+            Some(label) => format!(
+                "    {} -->|\"{}\"| {}",
+                from,
+                escape_text(&label.to_string()),
+                to
+            ),
+            None => format!("    {} --> {}", from, to),
+        };
+        self.edges.push(line);
+    }
+}
+
+impl MermaidExporter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn whole_graph(mut self, whole_graph: bool) -> Self {
+        self.whole_graph = whole_graph;
+        self
+    }
+
+    pub fn export_mermaid(&mut self, graph: &Graph) -> String {
+        self.palette = graph.palette().map(String::from);
+        if self.whole_graph {
+            graph.export_all(self);
+        } else {
+            graph.export(self);
+        }
+
+        let direction = if self.is_left_right { "LR" } else { "TB" };
+        let mut lines = vec![format!("flowchart {}", direction)];
+        lines.extend(self.nodes.iter().cloned());
+        lines.extend(self.edges.iter().cloned());
+        for (class, (fill, members)) in &self.classes {
+            lines.push(format!(
+                "    classDef {} fill:{},color:{}",
+                class,
+                fill,
+                fill.text_color()
+            ));
+            lines.push(format!("    class {} {}", members.join(","), class));
+        }
+        lines.extend(self.styles.iter().cloned());
+
+        lines.join("\n")
+    }
+}
+
+/// mermaid class names can't contain the '#', so '#project-x' becomes 'tag_project-x'
+fn class_name(hash_tag: &str) -> String {
+    format!("tag_{}", hash_tag.trim_start_matches('#'))
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+fn unescape_text(text: &str) -> String {
+    text.replace("#quot;", "\"")
+}
+
+/// Reads a mermaid `flowchart` or `graph` block back into a graph. Node ids are kept as they
+/// are, and edges are given fresh ids, since mermaid doesn't have them. Styling is ignored.
+pub struct MermaidImporter {
+    content: String,
+}
+
+struct NodeRef {
+    id: String,
+    text: Option<String>,
+}
+
+struct Link {
+    label: Option<String>,
+    to: NodeRef,
+}
+
+impl MermaidImporter {
+    pub fn new<S: Into<String>>(content: S) -> Self {
+        MermaidImporter {
+            content: content.into(),
+        }
+    }
+
+    pub fn import(&self) -> Result<Graph, anyhow::Error> {
+        let mut graph = Graph::new();
+        let mut seen_header = false;
+        let mut labels: Vec<(String, Option<String>)> = vec![];
+        let mut edges: Vec<(String, String, Option<String>)> = vec![];
+
+        for (line_number, line) in self.content.lines().enumerate() {
+            for statement in split_statements(line) {
+                let statement = statement.trim();
+
+                // fences, when pasted straight out of markdown, and comments.
+                if statement.is_empty()
+                    || statement.starts_with("```")
+                    || statement.starts_with("%%")
+                {
+                    continue;
+                }
+
+                if !seen_header {
+                    let is_left_right = header().parse(statement.as_bytes()).map_err(|_| {
+                        anyhow!("expected 'flowchart' or 'graph', got '{}'", statement)
+                    })?;
+                    graph.set_direction(is_left_right);
+                    seen_header = true;
+                    continue;
+                }
+
+                if is_ignored(statement) {
+                    continue;
+                }
+
+                let (first, links) =
+                    statement_parser()
+                        .parse(statement.as_bytes())
+                        .map_err(|_| {
+                            anyhow!(
+                                "could not understand line {}: '{}'",
+                                line_number + 1,
+                                statement
+                            )
+                        })?;
+
+                let mut from = first.id.clone();
+                remember_node(&mut labels, first);
+                for link in links {
+                    let to = link.to.id.clone();
+                    remember_node(&mut labels, link.to);
+                    edges.push((from, to.clone(), link.label));
+                    from = to;
+                }
+            }
+        }
+
+        if !seen_header {
+            return Err(anyhow!("no flowchart found"));
+        }
+
+        for (id, text) in labels {
+            let label = text.unwrap_or_else(|| id.clone());
+            graph.insert_node_with_id(Id::new(id), Label::new(label))?;
+        }
+
+        for (from, to, label) in edges {
+            graph.link_edge(&Id::new(from), &Id::new(to), label.map(Label::new))?;
+        }
+
+        Ok(graph)
+    }
+}
+
+/// nodes are declared the first time they're mentioned; the text can come from any mention.
+fn remember_node(labels: &mut Vec<(String, Option<String>)>, node: NodeRef) {
+    match labels.iter_mut().find(|(id, _)| *id == node.id) {
+        Some((_, text)) => {
+            if node.text.is_some() {
+                *text = node.text;
+            }
+        }
+        None => labels.push((node.id, node.text)),
+    }
+}
+
+fn is_ignored(statement: &str) -> bool {
+    let keyword = statement.split_whitespace().next().unwrap_or_default();
+    matches!(
+        keyword,
+        "classDef" | "class" | "style" | "linkStyle" | "click" | "subgraph" | "end" | "direction"
+    )
+}
+
+/// split on ';', which can separate statements, but not inside quoted text.
+fn split_statements(line: &str) -> Vec<&str> {
+    let mut statements = vec![];
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                statements.push(&line[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&line[start..]);
+    statements
+}
+
+fn space<'a>() -> Parser<'a, u8, ()> {
+    one_of(b" \t").repeat(0..).discard()
+}
+
+fn to_string(u8s: Vec<u8>) -> String {
+    String::from_utf8(u8s).expect("can only parse utf")
+}
+
+/// `flowchart LR` or `graph TD`; true when the graph runs left to right.
+fn header<'a>() -> Parser<'a, u8, bool> {
+    let keyword = seq(b"flowchart") | seq(b"graph");
+    let direction = seq(b"LR") | seq(b"RL") | seq(b"TB") | seq(b"TD") | seq(b"BT");
+    let direction = (space() * direction).opt();
+    (keyword * direction - space() - end())
+        .map(|direction| matches!(direction, Some(b"LR") | Some(b"RL")))
+}
+
+fn node_id<'a>() -> Parser<'a, u8, String> {
+    is_a(|c: u8| c.is_ascii_alphanumeric() || c == b'_')
+        .repeat(1..)
+        .map(to_string)
+}
+
+fn quoted<'a>() -> Parser<'a, u8, String> {
+    (sym(b'"') * none_of(b"\"").repeat(0..) - sym(b'"')).map(to_string)
+}
+
+/// the text in any of the node shapes, like `[box]`, `(round)`, `{diamond}` or `((circle))`
+fn node_text<'a>() -> Parser<'a, u8, String> {
+    let open = one_of(b"[({>").repeat(1..3);
+    let bare = none_of(b"\"[](){}").repeat(1..).map(to_string);
+    let close = one_of(b"])}").repeat(1..3);
+    (open * (quoted() | bare) - close).map(|text| unescape_text(text.trim()))
+}
+
+fn node_ref<'a>() -> Parser<'a, u8, NodeRef> {
+    let class = seq(b":::") * node_id();
+    (node_id() + node_text().opt() - class.opt()).map(|(id, text)| NodeRef { id, text })
+}
+
+/// `-->`, `---`, `-.->`, `==>` and friends
+fn arrow<'a>() -> Parser<'a, u8, ()> {
+    (sym(b'<').opt() * one_of(b"-=.").repeat(2..) * one_of(b">ox").opt()).discard()
+}
+
+fn link<'a>() -> Parser<'a, u8, Option<String>> {
+    // a -->|label| b
+    let piped_text = quoted() | none_of(b"|").repeat(1..).map(to_string);
+    let piped = arrow() * space() * (sym(b'|') * piped_text - sym(b'|')).opt();
+
+    // a -- label --> b
+    let inline_text = (!seq(b"--") * any()).repeat(1..).map(to_string);
+    let inline = seq(b"--") * one_of(b" \t").repeat(1..) * inline_text - arrow();
+
+    (inline.map(Some) | piped).map(|text| text.map(|t| unescape_text(t.trim())))
+}
+
+fn statement_parser<'a>() -> Parser<'a, u8, (NodeRef, Vec<Link>)> {
+    let link = (space() * link() - space() + node_ref()).map(|(label, to)| Link { label, to });
+    node_ref() + link.repeat(0..) - space() - end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdot_core::command::GraphCommand;
+
+    fn business_graph() -> Graph {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("idea #product"));
+        graph.insert_node(Label::new("\"launch\" #product #marketing"));
+        graph
+            .link_edge(&Id::new("n0"), &Id::new("n1"), Some(Label::new("funds")))
+            .unwrap();
+        graph
+            .apply_command(GraphCommand::SetDirection {
+                is_left_right: true,
+            })
+            .unwrap();
+        graph
+    }
+
+    #[test]
+    fn exports_flowchart() {
+        let mut graph = business_graph();
+        graph.highlight_search_results(Label::new("idea"));
+
+        let exported = MermaidExporter::new().export_mermaid(&graph);
+        let lines: Vec<_> = exported.lines().collect();
+
+        assert_eq!(lines[0], "flowchart LR");
+        assert_eq!(lines[1], "    n0[\"idea #product\"]");
+        assert_eq!(
+            lines[2],
+            "    n1[\"#quot;launch#quot; #product #marketing\"]"
+        );
+        assert_eq!(lines[3], "    n0 -->|\"funds\"| n1");
+        assert!(exported.contains("    class n1 tag_marketing"));
+        assert!(exported.contains("    class n0,n1 tag_product"));
+        assert!(exported.contains("    style n0 fill:#D0CCCC,color:#000000"));
+    }
+
+    #[test]
+    fn round_trips_flowchart() {
+        let exported = MermaidExporter::new().export_mermaid(&business_graph());
+        let imported = MermaidImporter::new(exported.clone())
+            .import()
+            .expect("could not import");
+
+        assert_eq!(
+            imported.find_node_label(&Id::new("n1")),
+            Some(Label::new("\"launch\" #product #marketing"))
+        );
+        assert_eq!(MermaidExporter::new().export_mermaid(&imported), exported);
+    }
+
+    #[test]
+    fn imports_hand_written_flowchart() {
+        let content = r#"
+```mermaid
+graph TD;
+    %% a comment
+    A[Christmas] -->|Get money| B(Go shopping)
+    B --> C{Let me think}
+    C -- One --> D[Laptop] --> E
+    C -.-> A
+    style A fill:#f9f
+```
+"#;
+        let mut graph = MermaidImporter::new(content)
+            .import()
+            .expect("could not import");
+
+        assert_eq!(
+            graph.find_node_label(&Id::new("C")),
+            Some(Label::new("Let me think"))
+        );
+        assert_eq!(graph.find_node_label(&Id::new("E")), Some(Label::new("E")));
+
+        let exported = MermaidExporter::new().export_mermaid(&graph);
+        assert!(exported.starts_with("flowchart TB"));
+        assert!(exported.contains("    A -->|\"Get money\"| B"));
+        assert!(exported.contains("    C -->|\"One\"| D"));
+        assert!(exported.contains("    D --> E"));
+        assert!(exported.contains("    C --> A"));
+
+        // new nodes don't clash with the imported ids
+        let (id, _) = graph.insert_node(Label::new("new"));
+        assert_eq!(id, Id::new("n0"));
+    }
+
+    #[test]
+    fn rejects_unknown_syntax() {
+        assert!(MermaidImporter::new("sequenceDiagram").import().is_err());
+        assert!(MermaidImporter::new("flowchart LR\n  A --> B\n  A ?? B")
+            .import()
+            .is_err());
+    }
+}
@@ -1,6 +1,171 @@
 use crate::colors::{Color, Colors};
-use palette::{FromColor, Hue, IntoColor, Lch, Srgb};
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use palette::{Clamp, ColorDifference, FromColor, Hue, IntoColor, Lab, Lch, LinSrgb, Srgb};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
 use std::collections::{HashMap, VecDeque};
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+
+/// the palette hashtags are coloured from when a graph hasn't chosen one.
+pub const DEFAULT_PALETTE: &str = "generated";
+
+/// the contrast WCAG's strictest level asks of small text, which hashtags are drawn in. Black or
+/// white text always manages 4.5:1, the usual level, so that's no use for finding weak colours.
+pub const MIN_TEXT_CONTRAST: f64 = 7.0;
+
+static PALETTES: OnceCell<PaletteCollection> = OnceCell::new();
+
+/// every palette there is; the built-in ones, and any `load_user_palettes` has added. They're
+/// only read once.
+pub fn palettes() -> &'static PaletteCollection {
+    PALETTES.get_or_init(|| builtin_palettes().expect("the built-in palettes should be readable"))
+}
+
+fn builtin_palettes() -> Result<PaletteCollection> {
+    let mut result = PaletteReader {}.read(include_str!("./palettes.txt"))?;
+
+    let generator = ColorIterator::new();
+    let colors: Vec<_> = generator.take(20).collect();
+    result.insert(DEFAULT_PALETTE, Palette { colors });
+
+    for (name, visions) in COLOR_BLIND_PALETTES {
+        let colors = distinguishable_colors(visions, COLOR_BLIND_PALETTE_SIZE);
+        result.insert(name, Palette { colors });
+    }
+    Ok(result)
+}
+
+/// where users can keep palettes of their own: `.txt` files in the same format as the built-in
+/// `palettes.txt`, and `.json` files downloaded from Khroma, which are named after the file.
+pub fn user_palettes_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("microdot").join("palettes"))
+}
+
+/// add the palettes in `dir`, if there is one, to the built-in ones. It has to be done before
+/// anything is drawn, since the palettes are fixed once they've been read.
+pub fn load_user_palettes(dir: &Path) -> Result<()> {
+    let mut result = builtin_palettes()?;
+
+    if dir.is_dir() {
+        let mut files: Vec<_> = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        files.sort();
+
+        for file in files {
+            read_palette_file(&file, &mut result)
+                .with_context(|| format!("reading palettes from {}", file.to_string_lossy()))?;
+        }
+    }
+
+    PALETTES
+        .set(result)
+        .map_err(|_| anyhow!("the palettes have already been read"))
+}
+
+fn read_palette_file(file: &Path, palettes: &mut PaletteCollection) -> Result<()> {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("txt") => {
+            let content = std::fs::read_to_string(file)?;
+            palettes.extend(PaletteReader {}.read(&content)?);
+        }
+        Some("json") => {
+            let content = std::fs::read_to_string(file)?;
+            let name = file.file_stem().unwrap_or_default().to_string_lossy();
+            palettes.insert(&name, read_khroma(&content)?);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// a palette from the json Khroma lets you download once it's trained; that's the `#RRGGBB`
+/// code in every "color" field, however deeply it's nested, in order, without repeats. Anything
+/// else in a "color" field is an error, with the line it's on.
+pub fn read_khroma(content: &str) -> Result<Palette> {
+    let KhromaColors(found) = serde_json::from_str(content)?;
+
+    let mut colors = vec![];
+    for color in found {
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    }
+    if colors.is_empty() {
+        return Err(anyhow!("no colours found"));
+    }
+    Ok(Palette { colors })
+}
+
+/// every colour in a piece of Khroma json; see `read_khroma`.
+struct KhromaColors(Vec<Color>);
+
+impl<'de> Deserialize<'de> for KhromaColors {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(KhromaVisitor)
+    }
+}
+
+struct KhromaVisitor;
+
+impl<'de> Visitor<'de> for KhromaVisitor {
+    type Value = KhromaColors;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("Khroma data")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut colors = vec![];
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "color" {
+                let code: String = map.next_value()?;
+                let color = Color::from_html_string(&code).map_err(|_| {
+                    de::Error::custom(format!("expected a #RRGGBB colour, not \"{}\"", code))
+                })?;
+                colors.push(color);
+            } else {
+                let KhromaColors(inner) = map.next_value()?;
+                colors.extend(inner);
+            }
+        }
+        Ok(KhromaColors(colors))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut colors = vec![];
+        while let Some(KhromaColors(inner)) = seq.next_element()? {
+            colors.extend(inner);
+        }
+        Ok(KhromaColors(colors))
+    }
+
+    // everything else, like names and ratings, has no colours in it
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(KhromaColors(vec![]))
+    }
+}
 
 #[derive(Clone)]
 pub struct Palette {
@@ -20,6 +185,15 @@ impl Palette {
         let index = index % self.colors.len();
         self.colors[index]
     }
+
+    /// the colours which text is hard to read on, even in black or white, whichever suits.
+    pub fn low_contrast_colors(&self) -> Vec<Color> {
+        self.colors
+            .iter()
+            .filter(|color| color.contrast_ratio(&color.text_color()) < MIN_TEXT_CONTRAST)
+            .copied()
+            .collect()
+    }
 }
 
 pub struct PaletteReader {}
@@ -39,9 +213,38 @@ impl PaletteCollection {
         self.inner.insert(name.to_string(), palette);
     }
 
+    fn extend(&mut self, other: PaletteCollection) {
+        self.inner.extend(other.inner);
+    }
+
     pub fn get(&self, name: &str) -> Option<Palette> {
         self.inner.get(name).cloned()
     }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.inner.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// a warning for each palette with colours that hashtags are hard to read on, by name.
+    pub fn lint(&self) -> Vec<String> {
+        self.names()
+            .into_iter()
+            .filter_map(|name| {
+                let colors = self.inner[name].low_contrast_colors();
+                if colors.is_empty() {
+                    return None;
+                }
+                let colors: Vec<_> = colors.iter().map(Color::to_html_string).collect();
+                Some(format!(
+                    "{}: hashtags are hard to read on {}",
+                    name,
+                    colors.join(" ")
+                ))
+            })
+            .collect()
+    }
 }
 
 impl PaletteReader {
@@ -79,14 +282,150 @@ impl PaletteReader {
             result.insert(name, palette);
         }
 
-        let generator = ColorIterator::new();
-        let colors: Vec<_> = generator.take(20).collect();
-        let palette = Palette { colors };
-        result.insert("generated", palette);
         Ok(result)
     }
 }
 
+/// the generated palettes which are safe for colour blind people, and who they have to suit.
+/// Everyone has to be able to tell the colours apart, not just people with one kind.
+const COLOR_BLIND_PALETTES: [(&str, &[ColorVision]); 4] = [
+    (
+        "protanopia_safe",
+        &[ColorVision::Normal, ColorVision::Protanopia],
+    ),
+    (
+        "deuteranopia_safe",
+        &[ColorVision::Normal, ColorVision::Deuteranopia],
+    ),
+    (
+        "tritanopia_safe",
+        &[ColorVision::Normal, ColorVision::Tritanopia],
+    ),
+    (
+        "colour_blind_safe",
+        &[
+            ColorVision::Normal,
+            ColorVision::Protanopia,
+            ColorVision::Deuteranopia,
+            ColorVision::Tritanopia,
+        ],
+    ),
+];
+
+/// past a dozen or so, the colours a colour blind person can tell apart get too close together.
+const COLOR_BLIND_PALETTE_SIZE: usize = 12;
+
+/// the ways people see colour which palettes can be generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorVision {
+    Normal,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl ColorVision {
+    /// how a colour looks to someone who sees this way, as an estimate in Lab, the space colour
+    /// differences are measured in. The simulations are Machado, Oliveira and Fernandes' (2009),
+    /// at full severity.
+    pub fn see(&self, color: &Color) -> Lab {
+        let matrix = match self {
+            ColorVision::Normal => return Lab::from_color(color.to_srgb().into_linear()),
+            ColorVision::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorVision::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorVision::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        };
+
+        let (r, g, b) = color.to_srgb().into_linear().into_components();
+        let row = |i: usize| {
+            let [x, y, z] = matrix[i];
+            (x * r + y * g + z * b).clamp(0.0, 1.0)
+        };
+        Lab::from_color(LinSrgb::new(row(0), row(1), row(2)))
+    }
+}
+
+/// up to `count` colours which stay as far apart as they can for everyone in `visions`. Each one
+/// is the candidate furthest from those already chosen, and from the white of a node, as seen by
+/// whoever finds it hardest to tell apart. Only colours hashtags are easy to read on are used.
+pub fn distinguishable_colors(visions: &[ColorVision], count: usize) -> Vec<Color> {
+    let candidates: Vec<_> = candidate_colors()
+        .into_iter()
+        .filter(|color| color.contrast_ratio(&color.text_color()) >= MIN_TEXT_CONTRAST)
+        .map(|color| {
+            let seen: Vec<_> = visions.iter().map(|vision| vision.see(&color)).collect();
+            (color, seen)
+        })
+        .collect();
+
+    let difference = |a: &[Lab], b: &[Lab]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.get_color_difference(b))
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    let white: Vec<_> = visions
+        .iter()
+        .map(|vision| vision.see(&Colors::white()))
+        .collect();
+    let mut nearest: Vec<_> = candidates
+        .iter()
+        .map(|(_, seen)| difference(seen, &white))
+        .collect();
+
+    let mut colors = vec![];
+    while colors.len() < count {
+        let furthest = nearest
+            .iter()
+            .enumerate()
+            .filter(|(_, distance)| **distance > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i);
+        let furthest = match furthest {
+            Some(i) => i,
+            None => break,
+        };
+
+        let (color, seen) = &candidates[furthest];
+        colors.push(*color);
+        for (distance, (_, other)) in nearest.iter_mut().zip(&candidates) {
+            *distance = distance.min(difference(seen, other));
+        }
+    }
+    colors
+}
+
+/// a spread of colours across the sRGB gamut to choose palettes from, stepping through LCh.
+fn candidate_colors() -> Vec<Color> {
+    let mut colors = vec![];
+    for lightness in (20..=95).step_by(5) {
+        for chroma in (20..=80).step_by(20) {
+            for hue in (0..360).step_by(15) {
+                let lch = Lch::new(lightness as f32, chroma as f32, hue as f32);
+                let rgb = Srgb::from_color(lch);
+                if rgb.is_within_bounds() {
+                    let (r, g, b) = rgb.into_format::<u8>().into_components();
+                    colors.push(Color::from_rgb(r, g, b));
+                }
+            }
+        }
+    }
+    colors
+}
+
 #[allow(dead_code)]
 pub struct ColorIterator {
     hue: f32,
@@ -162,4 +501,113 @@ mod tests {
         let palettes = reader.read(content).unwrap();
         assert!(palettes.get("antarctica_evening").is_some());
     }
+
+    #[test]
+    fn lints_low_contrast_colors() {
+        let content = include_str!("./palettes.txt");
+        let palettes = PaletteReader {}.read(content).unwrap();
+        let warnings = palettes.lint();
+        for warning in &warnings {
+            println!("{}", warning);
+        }
+
+        assert!(
+            warnings.contains(&"rainbow: hashtags are hard to read on #ED4145 #177C99".to_string())
+        );
+        assert!(!warnings.iter().any(|w| w.starts_with("soft_nature")));
+    }
+
+    fn closest_pair(colors: &[Color], vision: ColorVision) -> f32 {
+        let seen: Vec<_> = colors.iter().map(|color| vision.see(color)).collect();
+        let mut closest = f32::INFINITY;
+        for (i, a) in seen.iter().enumerate() {
+            for b in &seen[i + 1..] {
+                closest = closest.min(a.get_color_difference(b));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn simulates_colour_blindness() {
+        let red = Color::from_rgb(255, 0, 0);
+        let green = Color::from_rgb(0, 128, 0);
+        let normal = ColorVision::Normal;
+        let deuteranopia = ColorVision::Deuteranopia;
+
+        assert!(
+            normal.see(&red).get_color_difference(&normal.see(&green))
+                > deuteranopia
+                    .see(&red)
+                    .get_color_difference(&deuteranopia.see(&green))
+        );
+    }
+
+    #[test]
+    fn colour_blind_palettes_are_easier_to_tell_apart() {
+        let generated: Vec<_> = ColorIterator::new()
+            .take(COLOR_BLIND_PALETTE_SIZE)
+            .collect();
+
+        for (name, visions) in COLOR_BLIND_PALETTES {
+            let palette = palettes().get(name).unwrap();
+            assert_eq!(palette.colors.len(), COLOR_BLIND_PALETTE_SIZE, "{}", name);
+            assert!(palette.low_contrast_colors().is_empty(), "{}", name);
+
+            for vision in visions {
+                let safe = closest_pair(&palette.colors, *vision);
+                assert!(
+                    safe > closest_pair(&generated, *vision),
+                    "{} for {:?}",
+                    name,
+                    vision
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn has_the_generated_palette_built_in() {
+        assert!(palettes().names().contains(&DEFAULT_PALETTE));
+        assert!(palettes().names().contains(&"rainbow"));
+    }
+
+    #[test]
+    fn can_read_khroma_data() {
+        let content = r##"{
+            "favorites": [
+                { "color": "#ED4145", "rating": 1 },
+                { "color": "#F1B02F", "rating": 1 },
+                { "color": "#ED4145", "rating": 0 }
+            ],
+            "name": "not a colour"
+        }"##;
+        let palette = read_khroma(content).unwrap();
+        assert_eq!(palette.get_fill_color(0), Color::from_rgb(237, 65, 69));
+        assert_eq!(palette.get_fill_color(1), Color::from_rgb(241, 176, 47));
+        assert_eq!(palette.get_fill_color(2), Color::from_rgb(237, 65, 69));
+
+        assert!(read_khroma(r#"{ "favorites": [] }"#).is_err());
+    }
+
+    #[test]
+    fn refuses_khroma_data_which_isnt_colours() {
+        let content = r##"{
+            "favorites": [
+                { "color": "#ED4145" },
+                { "color": "F1B02F" }
+            ]
+        }"##;
+        let error = read_khroma(content).err().unwrap().to_string();
+        assert!(error.contains(r#"expected a #RRGGBB colour, not "F1B02F""#));
+        assert!(error.contains("line 4"), "{}", error);
+
+        // and loading one says which file it's in
+        let dir = std::env::temp_dir().join("microdot-bad-khroma");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bad.json"), content).unwrap();
+        let error = format!("{:#}", load_user_palettes(&dir).err().unwrap());
+        assert!(error.contains("bad.json"), "{}", error);
+        assert!(error.contains("line 4"), "{}", error);
+    }
 }
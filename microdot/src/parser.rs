@@ -1,8 +1,10 @@
 use crate::Command;
 use microdot_core::command::GraphCommand;
+use microdot_core::graph::{Filter, Reach, Selection, TagExpr};
 use microdot_core::{Id, Label, Line};
-use pom::char_class::{alpha, alphanum, multispace};
+use pom::char_class::{alpha, alphanum, digit, multispace};
 use pom::parser::*;
+use std::path::PathBuf;
 
 /// space, tab, etc
 fn ws<'a>() -> Parser<'a, u8, ()> {
@@ -34,6 +36,14 @@ fn id<'a>() -> Parser<'a, u8, String> {
     spaced(it).name("name")
 }
 
+fn node_id<'a>() -> Parser<'a, u8, String> {
+    // n8; the ids the graph gives its nodes, rather than any name
+    let it = (sym(b'n') + is_a(digit).repeat(1..))
+        .map(|(n, digits)| format!("{}{}", n as char, String::from_utf8(digits).unwrap()));
+
+    spaced(it).name("node id")
+}
+
 fn is_cr(term: u8) -> bool {
     term == b'\r'
 }
@@ -68,6 +78,105 @@ fn print_json<'a>() -> Parser<'a, u8, ()> {
     (keyword(b"json") | keyword(b"j")).discard()
 }
 
+fn print_mermaid<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"mermaid").discard()
+}
+
+fn import<'a>() -> Parser<'a, u8, String> {
+    keyword(b"import") * label()
+}
+
+fn hash_tag<'a>() -> Parser<'a, u8, String> {
+    fn is_tag_char(term: u8) -> bool {
+        alphanum(term) || term == b'_' || term == b'-'
+    }
+
+    spaced(sym(b'#') * (is_a(alpha) + is_a(is_tag_char).repeat(0..)))
+        .map(|(first, rest)| format!("{}{}", first as char, String::from_utf8(rest).unwrap()))
+}
+
+fn selection<'a>() -> Parser<'a, u8, Selection> {
+    // up n1, down n1, #tag, or n1 n2 n3
+    (keyword(b"up") * id()).map(|id| Selection::Reach(Id::new(id), Reach::Upstream))
+        | (keyword(b"down") * id()).map(|id| Selection::Reach(Id::new(id), Reach::Downstream))
+        | hash_tag().map(Selection::HashTag)
+        | id()
+            .repeat(1..)
+            .map(|ids| Selection::Nodes(ids.into_iter().map(Id::new).collect()))
+}
+
+fn extract<'a>() -> Parser<'a, u8, (Selection, String)> {
+    // extract down n1 > slice.json
+    keyword(b"extract") * selection() - sym(b'>') + label()
+}
+
+fn tag_factor<'a>() -> Parser<'a, u8, TagExpr> {
+    // #tag, not #tag, (#a or #b)
+    (keyword(b"not") * call(tag_factor)).map(|expr| TagExpr::Not(Box::new(expr)))
+        | (spaced(sym(b'(')) * call(tag_expr) - spaced(sym(b')')))
+        | hash_tag().map(TagExpr::Tag)
+}
+
+fn tag_term<'a>() -> Parser<'a, u8, TagExpr> {
+    // 'and' binds tighter than 'or'
+    (tag_factor() + (keyword(b"and") * tag_factor()).repeat(0..)).map(|(first, rest)| {
+        rest.into_iter()
+            .fold(first, |lhs, rhs| TagExpr::And(Box::new(lhs), Box::new(rhs)))
+    })
+}
+
+fn tag_expr<'a>() -> Parser<'a, u8, TagExpr> {
+    // #a and not #b or #c
+    (tag_term() + (keyword(b"or") * tag_term()).repeat(0..)).map(|(first, rest)| {
+        rest.into_iter()
+            .fold(first, |lhs, rhs| TagExpr::Or(Box::new(lhs), Box::new(rhs)))
+    })
+}
+
+fn filter<'a>() -> Parser<'a, u8, Option<Filter>> {
+    // filter #a and not #b, filter #a with neighbours, filter off
+    let with_neighbours = (keyword(b"with") * keyword(b"neighbours"))
+        .opt()
+        .map(|w| w.is_some());
+    let filter = (tag_expr() + with_neighbours).map(|(expr, with_neighbours)| {
+        Some(Filter {
+            expr,
+            with_neighbours,
+        })
+    });
+
+    keyword(b"filter") * (literal(b"off").map(|_| None) | filter) - end()
+}
+
+fn palette<'a>() -> Parser<'a, u8, Option<String>> {
+    // palette, palette rainbow, palette my-palette; user palettes are named after their files,
+    // so anything without spaces goes.
+    let name = is_a(|term: u8| !multispace(term))
+        .repeat(1..)
+        .map(|u8s| String::from_utf8(u8s).expect("can only parse utf"));
+    keyword(b"palette") * spaced(name).opt() - end()
+}
+
+fn cluster<'a>() -> Parser<'a, u8, Vec<String>> {
+    // cluster #Frodo #Gandalf, cluster off
+    keyword(b"cluster") * (hash_tag().repeat(1..) | literal(b"off").map(|_| vec![])) - end()
+}
+
+fn merge_nodes<'a>() -> Parser<'a, u8, ((String, String), bool)> {
+    // fold n8 n3, fold n8 n3 join; merge n8 n3 too, so long as both are node ids rather
+    // than the words of a file name.
+    let nodes = (keyword(b"fold") * id() + id()) | (keyword(b"merge") * node_id() + node_id());
+    nodes + keyword(b"join").opt().map(|j| j.is_some()) - end()
+}
+
+fn merge_file<'a>() -> Parser<'a, u8, (bool, String)> {
+    // merge other.json, merge unify other.json
+    let unify = (seq(b"unify") * ws().repeat(1..))
+        .opt()
+        .map(|u| u.is_some());
+    keyword(b"merge") * (unify + label())
+}
+
 fn save<'a>() -> Parser<'a, u8, ()> {
     (keyword(b"save")).discard()
 }
@@ -83,6 +192,15 @@ fn exit<'a>() -> Parser<'a, u8, ()> {
 fn show<'a>() -> Parser<'a, u8, ()> {
     keyword(b"show").discard()
 }
+
+fn undo<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"undo").discard()
+}
+
+fn redo<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"redo").discard()
+}
+
 fn keyword<'a>(keyword: &'static [u8]) -> Parser<'a, u8, ()> {
     literal(keyword).discard().name("keyword")
 }
@@ -98,11 +216,58 @@ fn delete_node<'a>() -> Parser<'a, u8, String> {
     keyword(b"d") * id()
 }
 
+fn collapse_node<'a>() -> Parser<'a, u8, String> {
+    // col n1
+    keyword(b"col") * id()
+}
+
 fn select_node<'a>() -> Parser<'a, u8, String> {
     // d foo
     keyword(b"sel") * id()
 }
 
+fn upstream<'a>() -> Parser<'a, u8, String> {
+    // up n1
+    keyword(b"up") * id()
+}
+
+fn downstream<'a>() -> Parser<'a, u8, String> {
+    // down n1
+    keyword(b"down") * id()
+}
+
+fn strict_dag<'a>() -> Parser<'a, u8, bool> {
+    // dag on, dag off
+    keyword(b"dag") * (literal(b"on").map(|_| true) | literal(b"off").map(|_| false))
+}
+
+fn legend<'a>() -> Parser<'a, u8, bool> {
+    // legend on, legend off
+    keyword(b"legend") * (literal(b"on").map(|_| true) | literal(b"off").map(|_| false))
+}
+
+fn list_cycles<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"cycles")
+}
+
+fn shortest_path<'a>() -> Parser<'a, u8, (String, String)> {
+    // path n1 n2
+    keyword(b"path") * id() + id()
+}
+
+fn all_paths<'a>() -> Parser<'a, u8, (String, String)> {
+    // paths n1 n2
+    keyword(b"paths") * id() + id()
+}
+
+fn list_roots<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"roots")
+}
+
+fn list_leaves<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"leaves")
+}
+
 fn lr<'a>() -> Parser<'a, u8, ()> {
     keyword(b"lr")
 }
@@ -111,9 +276,14 @@ fn tb<'a>() -> Parser<'a, u8, ()> {
     keyword(b"tb")
 }
 
-fn link_edge<'a>() -> Parser<'a, u8, (String, String)> {
-    // e bar baz
-    keyword(b"l") * id() + id()
+fn link_edge<'a>() -> Parser<'a, u8, ((String, String), Option<String>)> {
+    // l bar baz optional label
+    keyword(b"l") * id() + id() + label().opt()
+}
+
+fn label_edge<'a>() -> Parser<'a, u8, (String, Option<String>)> {
+    // le e1 optional label
+    keyword(b"le") * id() + label().opt()
 }
 
 fn unlink_edge<'a>() -> Parser<'a, u8, String> {
@@ -121,6 +291,16 @@ fn unlink_edge<'a>() -> Parser<'a, u8, String> {
     keyword(b"u") * id()
 }
 
+fn reverse_edge<'a>() -> Parser<'a, u8, String> {
+    // rev e1
+    keyword(b"rev") * id() - end()
+}
+
+fn retarget_edge<'a>() -> Parser<'a, u8, ((String, String), String)> {
+    // ret e1 n1 n2
+    keyword(b"ret") * id() + id() + id() - end()
+}
+
 fn rename_node<'a>() -> Parser<'a, u8, (String, String)> {
     // e bar baz
     rename_node_unlabelled() + label()
@@ -149,6 +329,53 @@ fn expand_edge<'a>() -> Parser<'a, u8, (String, String)> {
 pub fn parse_line(line: Line) -> Command {
     let text = &line.to_string().into_bytes();
 
+    // these need to come before 'u', 'r' and 'le', which would otherwise read them as ids.
+    if let Ok(()) = undo().parse(text) {
+        return GraphCommand::Undo.into();
+    }
+
+    if let Ok(()) = redo().parse(text) {
+        return GraphCommand::Redo.into();
+    }
+
+    if let Ok(()) = list_roots().parse(text) {
+        return Command::ListRoots;
+    }
+
+    if let Ok(()) = list_leaves().parse(text) {
+        return Command::ListLeaves;
+    }
+
+    // needs to come before 'i', which would otherwise insert a node labelled 'mport ...'
+    if let Ok(path) = import().parse(text) {
+        return Command::Import {
+            path: PathBuf::from(path.trim()),
+        };
+    }
+
+    if let Ok(((from, into), join_labels)) = merge_nodes().parse(text) {
+        return GraphCommand::MergeNodes {
+            from: Id::new(from),
+            into: Id::new(into),
+            join_labels,
+        }
+        .into();
+    }
+
+    if let Ok((unify_labels, path)) = merge_file().parse(text) {
+        return Command::Merge {
+            path: PathBuf::from(path.trim()),
+            unify_labels,
+        };
+    }
+
+    if let Ok((selection, path)) = extract().parse(text) {
+        return Command::Extract {
+            selection,
+            path: PathBuf::from(path.trim()),
+        };
+    }
+
     if let Ok(res) = insert_node().parse(text) {
         return GraphCommand::InsertNode {
             label: Label::new(&res),
@@ -156,18 +383,64 @@ pub fn parse_line(line: Line) -> Command {
         .into();
     }
 
+    // needs to come before 'p', which would otherwise print the dot file
+    if let Ok(name) = palette().parse(text) {
+        return Command::Palette { name };
+    }
+
+    if let Ok(filter) = filter().parse(text) {
+        return Command::Filter { filter };
+    }
+
+    if let Ok(tags) = cluster().parse(text) {
+        return Command::Cluster { tags };
+    }
+
+    // needs to come before 'd', which would otherwise read 'dag' as deleting 'ag'
+    if let Ok(is_strict_dag) = strict_dag().parse(text) {
+        return GraphCommand::SetStrictDag { is_strict_dag }.into();
+    }
+
+    // needs to come before 'le' and 'l', which would otherwise label or link 'gend'
+    if let Ok(show_legend) = legend().parse(text) {
+        return GraphCommand::SetLegend { show_legend }.into();
+    }
+
+    // these need to come before 'd' and 'u' too.
+    if let Ok(id) = downstream().parse(text) {
+        return Command::Downstream { id: Id::new(id) };
+    }
+
+    if let Ok(id) = upstream().parse(text) {
+        return Command::Upstream { id: Id::new(id) };
+    }
+
     if let Ok(res) = delete_node().parse(text) {
         return GraphCommand::DeleteNode { id: Id::new(&res) }.into();
     }
 
+    if let Ok(res) = collapse_node().parse(text) {
+        return GraphCommand::CollapseNode { id: Id::new(&res) }.into();
+    }
+
     if let Ok(res) = select_node().parse(text) {
         return GraphCommand::SelectNode { id: Id::new(&res) }.into();
     }
 
-    if let Ok((from, to)) = link_edge().parse(text) {
+    // needs to come before 'l', which would otherwise read 'le' as the start of an id.
+    if let Ok((id, label)) = label_edge().parse(text) {
+        return GraphCommand::LabelEdge {
+            id: Id::new(&id),
+            label: label.map(Label::new),
+        }
+        .into();
+    }
+
+    if let Ok(((from, to), label)) = link_edge().parse(text) {
         return GraphCommand::LinkEdge {
             from: Id::new(&from),
             to: Id::new(&to),
+            label: label.map(Label::new),
         }
         .into();
     }
@@ -176,6 +449,20 @@ pub fn parse_line(line: Line) -> Command {
         return GraphCommand::UnlinkEdge { id: Id::new(&id) }.into();
     }
 
+    // these need to come before 'r', which would otherwise rename a node called 'ev' or 'et'.
+    if let Ok(id) = reverse_edge().parse(text) {
+        return GraphCommand::ReverseEdge { id: Id::new(id) }.into();
+    }
+
+    if let Ok(((id, from), to)) = retarget_edge().parse(text) {
+        return GraphCommand::RetargetEdge {
+            id: Id::new(id),
+            from: Id::new(from),
+            to: Id::new(to),
+        }
+        .into();
+    }
+
     if let Ok((id, label)) = rename_node().parse(text) {
         return GraphCommand::RenameNode {
             id: Id::new(&id),
@@ -224,6 +511,25 @@ pub fn parse_line(line: Line) -> Command {
         .into();
     }
 
+    if let Ok(()) = list_cycles().parse(text) {
+        return Command::ListCycles;
+    }
+
+    // these need to come before 'p', and 'paths' before 'path'.
+    if let Ok((from, to)) = all_paths().parse(text) {
+        return Command::AllPaths {
+            from: Id::new(from),
+            to: Id::new(to),
+        };
+    }
+
+    if let Ok((from, to)) = shortest_path().parse(text) {
+        return Command::ShortestPath {
+            from: Id::new(from),
+            to: Id::new(to),
+        };
+    }
+
     if let Ok(()) = exit().parse(text) {
         return Command::Exit;
     }
@@ -240,6 +546,10 @@ pub fn parse_line(line: Line) -> Command {
         return Command::PrintJson;
     }
 
+    if let Ok(()) = print_mermaid().parse(text) {
+        return Command::PrintMermaid;
+    }
+
     if let Ok(()) = save().parse(text) {
         return Command::Save;
     }
@@ -302,7 +612,21 @@ mod tests {
         assert_consumes_all![
             link_edge(),
             b"l f1 f2",
-            ("f1".to_string(), "f2".to_string())
+            (("f1".to_string(), "f2".to_string()), None)
+        ];
+        assert_consumes_all![
+            link_edge(),
+            b"l f1 f2 leads to",
+            (
+                ("f1".to_string(), "f2".to_string()),
+                Some("leads to".to_string())
+            )
+        ];
+        assert_consumes_all![label_edge(), b"le e1", ("e1".to_string(), None)];
+        assert_consumes_all![
+            label_edge(),
+            b"le e1 causes",
+            ("e1".to_string(), Some("causes".to_string()))
         ];
 
         assert_consumes_all![
@@ -337,15 +661,70 @@ mod tests {
         assert_consumes_all![print_dot(), b"print", ()];
         assert_consumes_all![print_json(), b"j", ()];
         assert_consumes_all![print_json(), b"json", ()];
+        assert_consumes_all![print_mermaid(), b"mermaid", ()];
+        assert_consumes_all![import(), b"import deps.dot", "deps.dot"];
         assert_consumes_all![save(), b"save", ()];
         assert_consumes_all![search(), b"/foo", "foo"];
         assert_consumes_all![search(), b"s foo", "foo"];
         assert_consumes_all![search(), b"search foo", "foo"];
         assert_consumes_all![select_node(), b"sel n1", "n1"];
+        assert_consumes_all![collapse_node(), b"col n1", "n1"];
         assert_consumes_all![show_help(), b"h", ()];
         assert_consumes_all![show_help(), b"help", ()];
         assert_consumes_all![tb(), b"tb"];
         assert_consumes_all![unlink_edge(), b"u e1", "e1"];
+        assert_consumes_all![undo(), b"undo", ()];
+        assert_consumes_all![redo(), b"redo", ()];
+        assert_consumes_all![strict_dag(), b"dag on", true];
+        assert_consumes_all![strict_dag(), b"dag off", false];
+        assert_consumes_all![legend(), b"legend on", true];
+        assert_consumes_all![legend(), b"legend off", false];
+        assert_consumes_all![list_cycles(), b"cycles", ()];
+        assert_consumes_all![upstream(), b"up n4", "n4"];
+        assert_consumes_all![list_roots(), b"roots", ()];
+        assert_consumes_all![hash_tag(), b"#project-x", "project-x"];
+        assert_consumes_all![
+            merge_nodes(),
+            b"fold n8 n3 join",
+            (("n8".to_string(), "n3".to_string()), true)
+        ];
+        assert_consumes_all![
+            merge_nodes(),
+            b"merge n8 n3",
+            (("n8".to_string(), "n3".to_string()), false)
+        ];
+        assert_consumes_all![
+            merge_file(),
+            b"merge unify other.json",
+            (true, "other.json".to_string())
+        ];
+        assert_consumes_all![
+            merge_file(),
+            b"merge unify.json",
+            (false, "unify.json".to_string())
+        ];
+        assert_consumes_all![
+            selection(),
+            b"n1 n2",
+            Selection::Nodes(vec![Id::new("n1"), Id::new("n2")])
+        ];
+        assert_consumes_all![
+            selection(),
+            b"up n4",
+            Selection::Reach(Id::new("n4"), Reach::Upstream)
+        ];
+        assert_consumes_all![
+            shortest_path(),
+            b"path n3 n27",
+            ("n3".to_string(), "n27".to_string())
+        ];
+        assert_consumes_all![
+            all_paths(),
+            b"paths n3 n27",
+            ("n3".to_string(), "n27".to_string())
+        ];
+        assert_consumes_all![list_leaves(), b"leaves", ()];
+        assert_consumes_all![downstream(), b"down n4", "n4"];
     }
 
     #[test]
@@ -364,6 +743,20 @@ mod tests {
         assert_parse_command!("j", Command::PrintJson);
 
         assert_parse_command!("exit", Command::Exit);
+
+        assert_parse_command!(
+            "import old graphs/deps.dot ",
+            Command::Import {
+                path: PathBuf::from("old graphs/deps.dot")
+            }
+        );
+        assert_parse_command!(
+            "i important",
+            GraphCommand::InsertNode {
+                label: Label::new("important")
+            }
+            .into()
+        );
         assert_parse_command!(
             "exp e1 foo",
             GraphCommand::ExpandEdge {
@@ -417,6 +810,11 @@ mod tests {
             GraphCommand::DeleteNode { id: Id::new("foo") }.into()
         );
 
+        assert_parse_command!(
+            "col foo",
+            GraphCommand::CollapseNode { id: Id::new("foo") }.into()
+        );
+
         assert_parse_command!(
             "sel foo",
             GraphCommand::SelectNode { id: Id::new("foo") }.into()
@@ -426,7 +824,36 @@ mod tests {
             "l foo bar",
             GraphCommand::LinkEdge {
                 from: Id::new("foo"),
-                to: Id::new("bar")
+                to: Id::new("bar"),
+                label: None
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "l foo bar because",
+            GraphCommand::LinkEdge {
+                from: Id::new("foo"),
+                to: Id::new("bar"),
+                label: Some(Label::new("because"))
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "le e1 because",
+            GraphCommand::LabelEdge {
+                id: Id::new("e1"),
+                label: Some(Label::new("because"))
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "le e1",
+            GraphCommand::LabelEdge {
+                id: Id::new("e1"),
+                label: None
             }
             .into()
         );
@@ -436,6 +863,34 @@ mod tests {
             GraphCommand::UnlinkEdge { id: Id::new("foo") }.into()
         );
 
+        assert_parse_command!(
+            "rev e4",
+            GraphCommand::ReverseEdge { id: Id::new("e4") }.into()
+        );
+
+        assert_parse_command!(
+            "ret e4 n1 n2",
+            GraphCommand::RetargetEdge {
+                id: Id::new("e4"),
+                from: Id::new("n1"),
+                to: Id::new("n2")
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "r revenue rises",
+            GraphCommand::RenameNode {
+                id: Id::new("revenue"),
+                label: Label::new("rises")
+            }
+            .into()
+        );
+
+        assert_parse_command!("undo", GraphCommand::Undo.into());
+
+        assert_parse_command!("redo", GraphCommand::Redo.into());
+
         assert_parse_command!(
             "r foo a new name",
             GraphCommand::RenameNode {
@@ -447,11 +902,164 @@ mod tests {
 
         assert_parse_command!(
             "r foo",
-            Command::RenameNodeUnlabelled { id: Id::new("foo") }.into()
+            Command::RenameNodeUnlabelled { id: Id::new("foo") }
         );
 
         assert_parse_command!("save", Command::Save);
 
+        assert_parse_command!(
+            "path n3 n27",
+            Command::ShortestPath {
+                from: Id::new("n3"),
+                to: Id::new("n27")
+            }
+        );
+
+        assert_parse_command!(
+            "paths n3 n27",
+            Command::AllPaths {
+                from: Id::new("n3"),
+                to: Id::new("n27")
+            }
+        );
+
+        assert_parse_command!(
+            "fold n8 n3",
+            GraphCommand::MergeNodes {
+                from: Id::new("n8"),
+                into: Id::new("n3"),
+                join_labels: false
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "merge notes draft",
+            Command::Merge {
+                path: PathBuf::from("notes draft"),
+                unify_labels: false
+            }
+        );
+
+        assert_parse_command!(
+            "merge n8 n3",
+            GraphCommand::MergeNodes {
+                from: Id::new("n8"),
+                into: Id::new("n3"),
+                join_labels: false
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "merge n8 n3 join",
+            GraphCommand::MergeNodes {
+                from: Id::new("n8"),
+                into: Id::new("n3"),
+                join_labels: true
+            }
+            .into()
+        );
+
+        assert_parse_command!(
+            "merge n8 notes",
+            Command::Merge {
+                path: PathBuf::from("n8 notes"),
+                unify_labels: false
+            }
+        );
+
+        assert_parse_command!(
+            "merge story parts/second.json",
+            Command::Merge {
+                path: PathBuf::from("story parts/second.json"),
+                unify_labels: false
+            }
+        );
+
+        assert_parse_command!(
+            "extract n1 n2 > slices/first.json",
+            Command::Extract {
+                selection: Selection::Nodes(vec![Id::new("n1"), Id::new("n2")]),
+                path: PathBuf::from("slices/first.json")
+            }
+        );
+
+        assert_parse_command!(
+            "cluster #Frodo #Gandalf",
+            Command::Cluster {
+                tags: vec!["Frodo".into(), "Gandalf".into()]
+            }
+        );
+
+        assert_parse_command!("cluster off", Command::Cluster { tags: vec![] });
+
+        assert_parse_command!("filter off", Command::Filter { filter: None });
+
+        assert_parse_command!("palette", Command::Palette { name: None });
+
+        assert_parse_command!(
+            "palette soft_coral",
+            Command::Palette {
+                name: Some("soft_coral".into())
+            }
+        );
+
+        assert_parse_command!(
+            "palette my-palette.v2",
+            Command::Palette {
+                name: Some("my-palette.v2".into())
+            }
+        );
+
+        let tag = |tag: &str| Box::new(TagExpr::Tag(tag.into()));
+        assert_parse_command!(
+            "filter #a or not #b and #c with neighbours",
+            Command::Filter {
+                filter: Some(Filter {
+                    expr: TagExpr::Or(
+                        tag("a"),
+                        Box::new(TagExpr::And(Box::new(TagExpr::Not(tag("b"))), tag("c")))
+                    ),
+                    with_neighbours: true
+                })
+            }
+        );
+
+        assert_parse_command!(
+            "filter not (#a or #b)",
+            Command::Filter {
+                filter: Some(Filter {
+                    expr: TagExpr::Not(Box::new(TagExpr::Or(tag("a"), tag("b")))),
+                    with_neighbours: false
+                })
+            }
+        );
+
+        assert_parse_command!(
+            "extract #sales > sales.json",
+            Command::Extract {
+                selection: Selection::HashTag("sales".into()),
+                path: PathBuf::from("sales.json")
+            }
+        );
+
+        assert_parse_command!(
+            "extract down n4 > effects.json",
+            Command::Extract {
+                selection: Selection::Reach(Id::new("n4"), Reach::Downstream),
+                path: PathBuf::from("effects.json")
+            }
+        );
+
+        assert_parse_command!("roots", Command::ListRoots);
+
+        assert_parse_command!("leaves", Command::ListLeaves);
+
+        assert_parse_command!("up n4", Command::Upstream { id: Id::new("n4") });
+
+        assert_parse_command!("down n4", Command::Downstream { id: Id::new("n4") });
+
         assert_parse_command!(
             "search foo",
             Command::Search {
@@ -479,7 +1087,7 @@ mod tests {
 
         let help_commands_line: Vec<_> = help_parser_content
             .split('\n')
-            .filter(|l| l.len() > 0)
+            .filter(|l| !l.is_empty())
             .collect();
 
         help_commands_line
@@ -0,0 +1,293 @@
+use anyhow::Result;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// how often an idle event stream is poked, so we notice browsers which have gone away.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>microdot</title>
+<style>body { margin: 0; padding: 1em; background: #FFFFFF; } img { max-width: 100%; }</style>
+</head>
+<body>
+<img id="graph" src="/graph.svg" alt="graph">
+<script>
+const events = new EventSource("/events");
+events.onmessage = (e) => {
+    document.getElementById("graph").src = "/graph.svg?v=" + e.data;
+};
+</script>
+</body>
+</html>
+"#;
+
+/// A small local http server showing the latest render, which tells the page to reload over
+/// server-sent events whenever a new one is published.
+pub struct PreviewServer {
+    address: SocketAddr,
+    state: Arc<PreviewState>,
+}
+
+#[derive(Default)]
+struct PreviewState {
+    render: Mutex<Render>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Render {
+    version: u64,
+    svg: String,
+}
+
+impl PreviewServer {
+    /// listen on localhost; port 0 picks any free port.
+    pub fn start(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(PreviewState::default());
+
+        let accept_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = accept_state.clone();
+                thread::spawn(move || {
+                    // a failure here only affects the one browser connection.
+                    let _ = handle_connection(stream, &state);
+                });
+            }
+        });
+
+        Ok(Self { address, state })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.address)
+    }
+
+    pub fn publish<S: Into<String>>(&self, svg: S) {
+        let mut render = self.state.render.lock().unwrap();
+        render.version += 1;
+        render.svg = svg.into();
+        self.state.changed.notify_all();
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &PreviewState) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // skip the headers; nothing we serve depends on them.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+
+    let mut stream = stream;
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", "");
+    }
+
+    match path {
+        "/" => respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            INDEX_HTML,
+        ),
+        "/graph.svg" => {
+            let svg = state.render.lock().unwrap().svg.clone();
+            respond(&mut stream, "200 OK", "image/svg+xml", &svg)
+        }
+        "/events" => stream_events(&mut stream, state),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found"),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn stream_events(stream: &mut TcpStream, state: &PreviewState) -> Result<()> {
+    // taken before replying, so nothing published once the browser is listening gets missed.
+    let mut seen = state.render.lock().unwrap().version;
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    loop {
+        let render = state.render.lock().unwrap();
+        let (render, _) = state
+            .changed
+            .wait_timeout_while(render, KEEP_ALIVE, |r| r.version == seen)
+            .unwrap();
+
+        let message = if render.version == seen {
+            ": keep-alive\n\n".to_string()
+        } else {
+            seen = render.version;
+            format!("data: {}\n\n", seen)
+        };
+        drop(render);
+
+        // a failed write means the browser has gone; stop streaming to it.
+        stream.write_all(message.as_bytes())?;
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::RenderMethod;
+    use crate::repl::{repl, ReplOptions};
+    use crate::Interaction;
+    use microdot_core::command::GraphCommand;
+    use microdot_core::graph::Graph;
+    use microdot_core::Label;
+    use std::io::Read;
+    use std::sync::RwLock;
+
+    fn get(server: &PreviewServer, path: &str) -> String {
+        get_from(server.address(), path)
+    }
+
+    fn get_from(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_the_page_and_latest_svg() {
+        let server = PreviewServer::start(0).unwrap();
+        server.publish("<svg>first</svg>");
+        server.publish("<svg>second</svg>");
+
+        let page = get(&server, "/");
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("EventSource"));
+
+        let svg = get(&server, "/graph.svg?v=2");
+        assert!(svg.contains("Content-Type: image/svg+xml"));
+        assert!(svg.ends_with("<svg>second</svg>"));
+
+        assert!(get(&server, "/nope").starts_with("HTTP/1.1 404"));
+    }
+
+    // looks at the preview once, before typing anything.
+    struct FirstLook {
+        address: SocketAddr,
+        svg: Option<String>,
+    }
+
+    impl Interaction for FirstLook {
+        fn read(&mut self, _prompt: &str) -> rustyline::Result<String> {
+            if self.svg.is_none() {
+                self.svg = Some(get_from(self.address, "/graph.svg"));
+            }
+            Err(rustyline::error::ReadlineError::Eof)
+        }
+
+        fn add_history<S: AsRef<str> + Into<String>>(&mut self, _history: S) -> bool {
+            true
+        }
+
+        fn log<S: AsRef<str> + Into<String>>(&mut self, _message: S) {}
+
+        fn should_compile(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn shows_the_existing_graph_before_any_input() {
+        let dir = std::env::temp_dir().join("microdot-first-look");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut graph = Graph::new();
+        graph
+            .apply_command(GraphCommand::InsertNode {
+                label: Label::new("already here"),
+            })
+            .unwrap();
+
+        let server = PreviewServer::start(0).unwrap();
+        let mut interaction = FirstLook {
+            address: server.address(),
+            svg: None,
+        };
+        let options = ReplOptions {
+            render_method: RenderMethod::Layered,
+            preview: Some(server),
+            ..Default::default()
+        };
+        repl(
+            &mut interaction,
+            &dir.join("graph.json"),
+            Arc::new(RwLock::new(graph)),
+            &options,
+        )
+        .unwrap();
+
+        assert!(interaction.svg.unwrap().contains("already here"));
+    }
+
+    #[test]
+    fn notifies_event_stream_of_new_renders() {
+        let server = PreviewServer::start(0).unwrap();
+
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(stream, "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("HTTP/1.1 200 OK"));
+        while line.trim() != "" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        server.publish("<svg/>");
+
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "data: 1\n");
+    }
+}
@@ -1,25 +1,140 @@
+use crate::dot::DotImporter;
 use crate::fdg::FdgExporter;
 use crate::graphviz::{DisplayMode, GraphVizExporter, OutputFormat};
-use crate::json::JsonExporter;
+use crate::json::{JsonExporter, JsonImporter};
+use crate::layered::LayeredExporter;
+use crate::mermaid::{MermaidExporter, MermaidImporter};
+use crate::palettes::{palettes, DEFAULT_PALETTE};
 use crate::parser::parse_line;
+use crate::preview::PreviewServer;
+use crate::styles::StyleRules;
 use crate::{graphviz, svg, Command, Interaction};
-use anyhow::{anyhow, Context, Result};
-use microdot_core::graph::Graph;
-use microdot_core::{CommandResult, Line};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use microdot_core::command::GraphCommand;
+use microdot_core::graph::{Cycles, Graph, PathCount, Reach, Selection};
+use microdot_core::{CommandResult, Id, Line};
 use rustyline::error::ReadlineError;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, LockResult, RwLock};
+use std::sync::{Arc, RwLock};
 
+#[derive(Default)]
+pub struct ReplOptions {
+    /// save the undo history next to the graph file, so it survives a restart.
+    pub persist_undo: bool,
+    pub render_method: RenderMethod,
+    /// also save a mermaid flowchart next to the graph file.
+    pub save_mermaid: bool,
+    /// when serving a live preview, each new render is pushed to it.
+    pub preview: Option<PreviewServer>,
+    /// apply commands to the graph in memory, but never write anything to disc.
+    pub dry_run: bool,
+    /// give up at the first command which fails, rather than carrying on.
+    pub stop_on_error: bool,
+    /// draw root causes and terminal effects differently in the rendered graph.
+    pub show_roles: bool,
+    /// how to draw the nodes carrying particular hashtags.
+    pub styles: StyleRules,
+}
+
+/// where the undo history for a graph is kept when it is persisted.
+pub fn undo_file(json_file: &Path) -> PathBuf {
+    json_file.with_extension("undo.json")
+}
+
+/// read a graph from a microdot json, mermaid or graphviz dot file, going by the extension.
+pub fn import_file(path: &Path) -> Result<Graph> {
+    let content = std::fs::read_to_string(path)?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match extension {
+        "json" => JsonImporter::new(content).import(),
+        "mmd" | "mermaid" => MermaidImporter::new(content).import(),
+        _ => DotImporter::new(content).import(),
+    }
+}
+
+/// merge the graph in another file into this one, listing where its nodes ended up, and
+/// returning whether to redraw.
+fn merge_file<I: Interaction>(
+    interaction: &mut I,
+    graph: &Arc<RwLock<Graph>>,
+    path: &Path,
+    unify_labels: bool,
+    failures: &mut usize,
+) -> bool {
+    let merged = import_file(path).and_then(|other| {
+        let mut graph = graph.write().unwrap();
+        let description = format!("merge {}", path.to_string_lossy());
+        Ok(graph.merge(&other, unify_labels, description)?)
+    });
+
+    match merged {
+        Ok((outcome, ids)) => {
+            interaction.log(format!("({})", outcome));
+            for (old, new) in ids {
+                if outcome.created_nodes.contains(&new) {
+                    interaction.log(format!("{} -> {}", old, new));
+                } else {
+                    interaction.log(format!("{} -> {} (same label)", old, new));
+                }
+            }
+            true
+        }
+        Err(e) => {
+            interaction.log(format!("could not merge {}: {}", path.to_string_lossy(), e));
+            *failures += 1;
+            false
+        }
+    }
+}
+
+/// write the selected part of the graph to its own microdot json file, leaving the graph alone.
+fn extract_file(
+    graph: &Graph,
+    selection: &Selection,
+    path: &Path,
+    json_file: &Path,
+    options: &ReplOptions,
+) -> Result<String> {
+    if path == json_file {
+        return Err(anyhow!("that's the file this graph is saved in"));
+    }
+
+    let slice = graph.extract(selection)?;
+    let message = format!(
+        "extracted {} nodes and {} edges to {}",
+        slice.node_count(),
+        slice.edge_count(),
+        path.to_string_lossy()
+    );
+
+    if options.dry_run {
+        return Ok(format!("dry run, not {}", message));
+    }
+
+    std::fs::write(path, JsonExporter::new().export_json(&slice))?;
+    Ok(message)
+}
+
+/// run commands until the input ends or the user exits, returning how many of them failed.
 pub fn repl<I: Interaction>(
     interaction: &mut I,
     json_file: &Path,
     graph: Arc<RwLock<Graph>>,
-) -> Result<()> {
+    options: &ReplOptions,
+) -> Result<usize> {
+    let mut failures = 0;
+    // hashtags whose nodes are grouped together in the graphviz render
+    let mut clusters: Vec<String> = vec![];
+
     loop {
-        let readline = interaction.read(">> ");
+        // when we start, make sure the existing pic is up to date, before waiting on any input.
+        compile_graph(interaction, json_file, &graph, options, &clusters)?;
 
-        // when we start, make sure the existing pic is up to date.
-        compile_graph(interaction, json_file, &graph)?;
+        let readline = interaction.read(">> ");
 
         let dirty = match readline {
             Ok(line) => {
@@ -32,9 +147,18 @@ pub fn repl<I: Interaction>(
                 match command {
                     Command::GraphCommand(graph_command) => {
                         let mut graph = graph.write().unwrap();
-                        let applied = graph.apply_command(graph_command);
-                        interaction.log(format!("({})", applied));
-                        true
+                        match graph.apply_command(graph_command) {
+                            Ok(outcome) => {
+                                interaction.log(format!("({})", outcome));
+                                true
+                            }
+                            Err(e) => {
+                                // nothing changed, so there's nothing to redraw
+                                interaction.log(format!("({})", e));
+                                failures += 1;
+                                false
+                            }
+                        }
                     }
                     Command::ShowHelp => {
                         interaction.log(include_str!("help.txt"));
@@ -45,6 +169,10 @@ pub fn repl<I: Interaction>(
                         false
                     }
                     Command::Show => {
+                        if let Some(preview) = &options.preview {
+                            interaction.log(format!("preview at {}", preview.url()));
+                            continue;
+                        }
                         let svg_file = json_file.with_extension("svg");
                         let svg_file = std::fs::canonicalize(svg_file)
                             .expect("could not canconcicalise file path");
@@ -54,7 +182,7 @@ pub fn repl<I: Interaction>(
                     }
                     Command::PrintDot => {
                         let graph = graph.read().unwrap();
-                        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+                        let mut exporter = dot_exporter(options, &clusters);
                         let out = exporter.export_dot(&graph);
                         interaction.log(out);
                         interaction.log("Dot printed");
@@ -68,79 +196,364 @@ pub fn repl<I: Interaction>(
                         interaction.log("Json printed");
                         false
                     }
+                    Command::PrintMermaid => {
+                        let graph = graph.read().unwrap();
+                        let mut exporter = MermaidExporter::new();
+                        let out = exporter.export_mermaid(&graph);
+                        interaction.log(out);
+                        interaction.log("Mermaid printed");
+                        false
+                    }
+                    Command::ListCycles => {
+                        let graph = graph.read().unwrap();
+                        let Cycles {
+                            cycles,
+                            is_complete,
+                        } = graph.find_cycles();
+                        if cycles.is_empty() {
+                            interaction.log("no cycles");
+                        } else {
+                            let lines: Vec<_> = cycles
+                                .iter()
+                                .map(|cycle| {
+                                    let ids: Vec<_> = cycle
+                                        .iter()
+                                        .chain(cycle.first())
+                                        .map(|id| id.to_string())
+                                        .collect();
+                                    ids.join(" -> ")
+                                })
+                                .collect();
+                            interaction.log(lines.join("\n"));
+                            if !is_complete {
+                                interaction.log(format!(
+                                    "(stopped after {} cycles; there may be more)",
+                                    cycles.len()
+                                ));
+                            }
+                        }
+                        false
+                    }
+                    Command::ShortestPath { from, to } => highlight_paths(
+                        interaction,
+                        &graph,
+                        (&from, &to),
+                        PathCount::Shortest,
+                        &mut failures,
+                    ),
+                    Command::AllPaths { from, to } => highlight_paths(
+                        interaction,
+                        &graph,
+                        (&from, &to),
+                        PathCount::All,
+                        &mut failures,
+                    ),
+                    Command::ListRoots => {
+                        let graph = graph.read().unwrap();
+                        interaction.log(list_nodes(&graph, &graph.find_roots(), "roots"));
+                        false
+                    }
+                    Command::ListLeaves => {
+                        let graph = graph.read().unwrap();
+                        interaction.log(list_nodes(&graph, &graph.find_leaves(), "leaves"));
+                        false
+                    }
                     Command::Search { sub_label } => {
                         let mut graph = graph.write().unwrap();
                         interaction.log(format!("({})", graph.highlight_search_results(sub_label)));
                         true
                     }
+                    Command::Upstream { id } => highlight_reachable(
+                        interaction,
+                        &graph,
+                        &id,
+                        Reach::Upstream,
+                        &mut failures,
+                    ),
+                    Command::Downstream { id } => highlight_reachable(
+                        interaction,
+                        &graph,
+                        &id,
+                        Reach::Downstream,
+                        &mut failures,
+                    ),
+                    Command::Merge { path, unify_labels } => {
+                        merge_file(interaction, &graph, &path, unify_labels, &mut failures)
+                    }
+                    Command::Extract { selection, path } => {
+                        let graph = graph.read().unwrap();
+                        match extract_file(&graph, &selection, &path, json_file, options) {
+                            Ok(message) => interaction.log(format!("({})", message)),
+                            Err(e) => {
+                                interaction.log(format!(
+                                    "could not extract to {}: {}",
+                                    path.to_string_lossy(),
+                                    e
+                                ));
+                                failures += 1;
+                            }
+                        }
+                        false
+                    }
+                    Command::Cluster { tags } => {
+                        if tags.is_empty() {
+                            interaction.log("(tagged nodes no longer grouped)");
+                        } else {
+                            let tags: Vec<_> = tags.iter().map(|tag| format!("#{}", tag)).collect();
+                            interaction.log(format!("(grouping nodes tagged {})", tags.join(", ")));
+                        }
+                        clusters = tags;
+                        true
+                    }
+                    Command::Palette { name: None } => {
+                        let graph = graph.read().unwrap();
+                        let current = graph.palette().unwrap_or(DEFAULT_PALETTE);
+                        let mut lines: Vec<_> = palettes()
+                            .names()
+                            .into_iter()
+                            .map(|name| {
+                                let marker = if name == current { "*" } else { " " };
+                                format!("{} {}", marker, name)
+                            })
+                            .collect();
+                        // black or white text always reads best, but it's small text
+                        lines.extend(palettes().lint().into_iter().map(|w| format!("({})", w)));
+                        interaction.log(lines.join("\n"));
+                        false
+                    }
+                    Command::Palette { name: Some(name) } => {
+                        if palettes().get(&name).is_none() {
+                            interaction.log(format!(
+                                "(no palette called {}; 'palette' lists them)",
+                                name
+                            ));
+                            failures += 1;
+                            false
+                        } else {
+                            let mut graph = graph.write().unwrap();
+                            match graph.apply_command(GraphCommand::SetPalette { name }) {
+                                Ok(outcome) => {
+                                    interaction.log(format!("({})", outcome));
+                                    true
+                                }
+                                Err(e) => {
+                                    interaction.log(format!("({})", e));
+                                    failures += 1;
+                                    false
+                                }
+                            }
+                        }
+                    }
+                    Command::Filter { filter } => {
+                        let mut graph = graph.write().unwrap();
+                        interaction.log(format!("({})", graph.set_filter(filter)));
+                        true
+                    }
+                    Command::Import { path } => match import_file(&path) {
+                        Ok(imported) => {
+                            let mut graph = graph.write().unwrap();
+                            let description = format!("import {}", path.to_string_lossy());
+                            interaction.log(format!("({})", graph.replace(imported, description)));
+                            true
+                        }
+                        Err(e) => {
+                            interaction.log(format!(
+                                "could not import {}: {}",
+                                path.to_string_lossy(),
+                                e
+                            ));
+                            failures += 1;
+                            false
+                        }
+                    },
                     Command::Save => {
                         interaction.log(format!("saving to {}", json_file.to_string_lossy()));
                         true
                     }
                     Command::ParseError { .. } => {
                         interaction.log("could not understand command; try 'h' for help");
+                        failures += 1;
                         false
                     }
-                    Command::Exit => return Ok(()),
+                    Command::Exit => return Ok(failures),
                 }
             }
             Err(ReadlineError::Interrupted) => {
                 interaction.log("CTRL-C");
 
-                return Ok(());
+                return Ok(failures);
             }
             Err(ReadlineError::Eof) => {
                 interaction.log("CTRL-D");
 
-                return Ok(());
+                return Ok(failures);
             }
             Err(err) => {
                 interaction.log(format!("Error: {:?}", err));
-                return Err(anyhow::anyhow!("readline error: {}", err.to_string()));
+                return Err(anyhow::anyhow!("readline error: {}", err));
             }
         };
 
         if dirty {
-            compile_graph(interaction, json_file, &graph)?;
+            compile_graph(interaction, json_file, &graph, options, &clusters)?;
+        }
+
+        if options.stop_on_error && failures > 0 {
+            return Ok(failures);
+        }
+    }
+}
+
+/// one line per node, with its label.
+fn list_nodes(graph: &Graph, ids: &[Id], description: &str) -> String {
+    if ids.is_empty() {
+        return format!("no {}", description);
+    }
+
+    let lines: Vec<_> = ids
+        .iter()
+        .map(|id| {
+            let label = graph
+                .find_node_label(id)
+                .map(|l| l.to_string())
+                .unwrap_or_default();
+            format!("{}: {}", id, label)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// list the nodes up or downstream of `id` and highlight them, returning whether to redraw.
+fn highlight_reachable<I: Interaction>(
+    interaction: &mut I,
+    graph: &Arc<RwLock<Graph>>,
+    id: &Id,
+    reach: Reach,
+    failures: &mut usize,
+) -> bool {
+    let mut graph = graph.write().unwrap();
+    match graph.highlight_reachable(id, reach) {
+        Ok(result) => {
+            interaction.log(format!("({})", result));
+            true
+        }
+        Err(e) => {
+            interaction.log(format!("({})", e));
+            *failures += 1;
+            false
+        }
+    }
+}
+
+/// print the paths between two nodes and highlight them, returning whether to redraw.
+fn highlight_paths<I: Interaction>(
+    interaction: &mut I,
+    graph: &Arc<RwLock<Graph>>,
+    (from, to): (&Id, &Id),
+    count: PathCount,
+    failures: &mut usize,
+) -> bool {
+    let mut graph = graph.write().unwrap();
+    match graph.highlight_paths(from, to, count) {
+        Ok(result) => {
+            interaction.log(format!("({})", result));
+            true
+        }
+        Err(e) => {
+            interaction.log(format!("({})", e));
+            *failures += 1;
+            false
         }
     }
 }
 
-enum RenderMethod {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, ValueEnum)]
+pub enum RenderMethod {
+    /// write a dot file and compile it with the graphviz `dot` binary
+    #[default]
+    #[clap(name = "graphviz")]
     GraphViz,
+    /// lay the graph out in ranks and write svg directly, without graphviz
+    Layered,
+    /// force-directed layout
     Fdg,
 }
 
-const RENDER_METHOD: RenderMethod = RenderMethod::GraphViz;
+impl RenderMethod {
+    /// graphviz when it's installed, otherwise the built-in layered layout.
+    pub fn detect() -> Self {
+        if graphviz::installed_graphviz_version().is_some() {
+            RenderMethod::GraphViz
+        } else {
+            RenderMethod::Layered
+        }
+    }
+}
 
 fn compile_graph<I: Interaction>(
     interaction: &mut I,
     json_file: &Path,
     graph: &Arc<RwLock<Graph>>,
+    options: &ReplOptions,
+    clusters: &[String],
 ) -> Result<()> {
+    if options.dry_run {
+        return Ok(());
+    }
+
     let graph = match graph.write() {
         Ok(graph) => graph,
         Err(e) => return Err(anyhow!(e.to_string())),
     };
-    match RENDER_METHOD {
+
+    if options.persist_undo {
+        std::fs::write(undo_file(json_file), graph.export_history())?;
+    }
+
+    save_json_file(json_file, &graph)?;
+
+    if options.save_mermaid {
+        save_mermaid_file(json_file, &graph)?;
+    }
+
+    let svg_file = match options.render_method {
         RenderMethod::GraphViz => {
-            let interactive_dot_file = save_dot_file(json_file, &graph)?;
+            let interactive_dot_file = save_dot_file(json_file, &graph, options, clusters)?;
+            if interaction.should_compile() {
+                compile_dot(&interactive_dot_file);
+                Some(interactive_dot_file.with_extension("svg"))
+            } else {
+                None
+            }
+        }
+        RenderMethod::Layered => {
             if interaction.should_compile() {
-                compile_dot(interactive_dot_file);
+                Some(compile_layered(json_file, &graph, options.show_roles)?)
+            } else {
+                None
             }
         }
         RenderMethod::Fdg => {
             if interaction.should_compile() {
-                compile_fdg(json_file, &graph)?;
+                Some(compile_fdg(json_file, &graph)?)
+            } else {
+                None
             }
         }
+    };
+
+    if let (Some(preview), Some(svg_file)) = (&options.preview, svg_file) {
+        // graphviz may have failed to write anything, in which case the last render stays up.
+        if let Ok(svg) = std::fs::read_to_string(svg_file) {
+            preview.publish(svg);
+        }
     }
 
     Ok(())
 }
 
 fn compile_fdg(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
-    let mut fdg_exporter = FdgExporter::default();
+    let fdg_exporter = FdgExporter::default();
     let svg = fdg_exporter.export(graph);
     let svg_file = json_file.with_extension("svg");
     std::fs::write(&svg_file, svg)?;
@@ -148,12 +561,48 @@ fn compile_fdg(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
     Ok(svg_file)
 }
 
-fn save_dot_file(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
+fn compile_layered(json_file: &Path, graph: &Graph, show_roles: bool) -> Result<PathBuf> {
+    let mut layered_exporter =
+        LayeredExporter::new(DisplayMode::Interactive).show_roles(show_roles);
+    let svg = layered_exporter.export_svg(graph);
+    let svg_file = json_file.with_extension("svg");
+    std::fs::write(&svg_file, svg)?;
+
+    Ok(svg_file)
+}
+
+fn save_json_file(json_file: &Path, graph: &Graph) -> Result<()> {
     let mut json_exporter = JsonExporter::new();
     let json = json_exporter.export_json(graph);
     std::fs::write(json_file, json)?;
 
-    let mut dot_exporter = GraphVizExporter::new(DisplayMode::Interactive);
+    Ok(())
+}
+
+fn save_mermaid_file(json_file: &Path, graph: &Graph) -> Result<()> {
+    // the saved flowchart stands in for the graph file, so the filter doesn't apply
+    let mut mermaid_exporter = MermaidExporter::new().whole_graph(true);
+    let mermaid = mermaid_exporter.export_mermaid(graph);
+    std::fs::write(json_file.with_extension("mmd"), mermaid)?;
+
+    Ok(())
+}
+
+/// a graphviz exporter set up the way the options and the session ask.
+fn dot_exporter(options: &ReplOptions, clusters: &[String]) -> GraphVizExporter {
+    GraphVizExporter::new(DisplayMode::Interactive)
+        .show_roles(options.show_roles)
+        .clusters(clusters)
+        .styles(&options.styles)
+}
+
+fn save_dot_file(
+    json_file: &Path,
+    graph: &Graph,
+    options: &ReplOptions,
+    clusters: &[String],
+) -> Result<PathBuf> {
+    let mut dot_exporter = dot_exporter(options, clusters);
     let interactive_dot = dot_exporter.export_dot(graph);
     let interactive_dot_file = json_file.with_extension("dot");
     std::fs::write(&interactive_dot_file, interactive_dot)?;
@@ -161,15 +610,15 @@ fn save_dot_file(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
     Ok(interactive_dot_file)
 }
 
-fn compile_dot(interactive_dot_file: PathBuf) -> CommandResult {
+fn compile_dot(interactive_dot_file: &Path) -> CommandResult {
     let svg_compile = graphviz::compile(
-        &interactive_dot_file,
+        interactive_dot_file,
         DisplayMode::Interactive,
         OutputFormat::Svg,
     );
 
     let png_compile = graphviz::compile(
-        &interactive_dot_file,
+        interactive_dot_file,
         DisplayMode::Interactive,
         OutputFormat::Png,
     );
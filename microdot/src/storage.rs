@@ -1,11 +1,13 @@
 use anyhow::{Context, Result};
 use std::path::{Path, PathBuf};
 
+#[allow(dead_code)]
 trait Store {
     fn read<P: AsRef<Path>>(&self, path: P) -> Result<String>;
     fn write<P: AsRef<Path>, S: AsRef<str>>(&self, path: P, content: S) -> Result<()>;
 }
 
+#[allow(dead_code)]
 struct FileStore {
     root: PathBuf,
 }
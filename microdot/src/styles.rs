@@ -0,0 +1,116 @@
+use crate::colors::Color;
+use anyhow::{Context, Result};
+use microdot_core::hash::HashTag;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// how to draw the nodes carrying a hashtag. Anything left out keeps the usual look.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagStyle {
+    /// the background of the hashtag's cell, instead of a colour from the palette
+    pub fill: Option<Color>,
+    pub border: Option<Color>,
+    /// a graphviz node shape, like `ellipse` or `note`
+    pub shape: Option<String>,
+    pub font: Option<String>,
+    pub penwidth: Option<f32>,
+}
+
+/// styles for hashtags, written as a json object from each tag to its `TagStyle`, eg
+///
+/// ```json
+/// { "#Risk": { "fill": "#ED4145", "shape": "octagon", "penwidth": 2 } }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StyleRules {
+    rules: HashMap<String, TagStyle>,
+}
+
+/// the rules for one graph, kept next to it.
+pub fn styles_file(json_file: &Path) -> PathBuf {
+    json_file.with_extension("styles.json")
+}
+
+/// the rules for every graph, in the microdot config directory.
+pub fn config_styles_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("microdot").join("styles.json"))
+}
+
+impl StyleRules {
+    pub fn parse(content: &str) -> Result<Self> {
+        let rules: HashMap<String, TagStyle> = serde_json::from_str(content)?;
+
+        // the '#' is optional in the file
+        let rules = rules
+            .into_iter()
+            .map(|(tag, style)| (format!("#{}", tag.trim_start_matches('#')), style))
+            .collect();
+        Ok(StyleRules { rules })
+    }
+
+    /// the rules in the config directory, overridden tag by tag by the ones next to the graph.
+    /// Neither file has to exist.
+    pub fn load(json_file: &Path) -> Result<Self> {
+        let mut rules = StyleRules::default();
+        for file in config_styles_file().iter().chain([&styles_file(json_file)]) {
+            if !file.exists() {
+                continue;
+            }
+            let content = std::fs::read_to_string(file)?;
+            let loaded = StyleRules::parse(&content)
+                .with_context(|| format!("reading styles from {}", file.to_string_lossy()))?;
+            rules.rules.extend(loaded.rules);
+        }
+        Ok(rules)
+    }
+
+    pub fn get(&self, tag: &HashTag) -> Option<&TagStyle> {
+        self.rules.get(&tag.to_string())
+    }
+
+    /// the style for a node with these tags; each part comes from the first tag which sets it.
+    pub fn for_tags(&self, tags: &[HashTag]) -> TagStyle {
+        let mut style = TagStyle::default();
+        for rule in tags.iter().filter_map(|tag| self.get(tag)) {
+            style.fill = style.fill.or(rule.fill);
+            style.border = style.border.or(rule.border);
+            style.shape = style.shape.or_else(|| rule.shape.clone());
+            style.font = style.font.or_else(|| rule.font.clone());
+            style.penwidth = style.penwidth.or(rule.penwidth);
+        }
+        style
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdot_core::hash::extract_hashtags;
+
+    #[test]
+    fn earlier_tags_win_each_part_of_the_style() {
+        let rules = StyleRules::parse(
+            r##"{
+                "#Done": { "fill": "#00FF00", "shape": "note" },
+                "Risk": { "fill": "#FF0000", "penwidth": 2 }
+            }"##,
+        )
+        .unwrap();
+
+        let (tags, _) = extract_hashtags("ship it #Risk #Done #Other");
+        let style = rules.for_tags(&tags);
+
+        assert_eq!(style.fill, Some(Color::from_rgb(0, 255, 0)));
+        assert_eq!(style.shape.as_deref(), Some("note"));
+        assert_eq!(style.penwidth, Some(2.0));
+        assert_eq!(style.border, None);
+    }
+
+    #[test]
+    fn refuses_bad_colours_and_unknown_settings() {
+        assert!(StyleRules::parse(r##"{ "#Risk": { "fill": "red" } }"##).is_err());
+        assert!(StyleRules::parse(r##"{ "#Risk": { "colour": "#FF0000" } }"##).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::graphviz::{compile, DisplayMode, GraphVizExporter, OutputFormat};
-use crate::repl::repl;
+use crate::repl::{repl, ReplOptions};
 use crate::Interaction;
 use anyhow::{anyhow, Result};
 use microdot_core::graph::Graph;
@@ -43,6 +43,7 @@ pub fn compile_input_string_content(text_file: PathBuf) -> PathBuf {
         &mut auto_interaction,
         &text_file.with_extension("json"),
         graph.clone(),
+        &ReplOptions::default(),
     )
     .expect("error in repl");
 
@@ -123,3 +124,43 @@ impl Interaction for AutoInteraction {
         false
     }
 }
+
+/// Runs the repl over a script, printing as it goes; for batch jobs rather than tests, so the
+/// graph is rendered as usual.
+pub struct ScriptInteraction {
+    lines: VecDeque<String>,
+}
+
+impl ScriptInteraction {
+    pub fn new(script: &str) -> Self {
+        Self {
+            lines: script.lines().map(|l| l.to_string()).collect(),
+        }
+    }
+}
+
+impl Interaction for ScriptInteraction {
+    fn read(&mut self, _prompt: &str) -> rustyline::Result<String> {
+        // blank lines and comments let scripts be laid out readably.
+        while let Some(line) = self.lines.pop_front() {
+            let trimmed = line.trim();
+            if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                return Ok(line);
+            }
+        }
+        Err(rustyline::error::ReadlineError::Eof)
+    }
+
+    fn add_history<S: AsRef<str> + Into<String>>(&mut self, history: S) -> bool {
+        println!(">> {}", history.as_ref());
+        true
+    }
+
+    fn log<S: AsRef<str> + Into<String>>(&mut self, message: S) {
+        println!("{}", message.into());
+    }
+
+    fn should_compile(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("microdot_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run_microdot(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_microdot"))
+        .args(args)
+        .args(["--render", "layered"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start microdot");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn runs_a_script_file() {
+    let dir = scratch_dir("script");
+    let script = dir.join("cmds.txt");
+    let graph = dir.join("graph.json");
+    std::fs::write(&script, "# build it\ni first\n\ni second\nl n0 n1 then\n").unwrap();
+
+    let output = run_microdot(
+        &[
+            "--file",
+            graph.to_str().unwrap(),
+            "--script",
+            script.to_str().unwrap(),
+        ],
+        "",
+    );
+
+    assert!(output.status.success());
+    let json = std::fs::read_to_string(&graph).unwrap();
+    assert!(json.contains("second"));
+    assert!(json.contains("\"label\": \"then\""));
+    assert!(dir.join("graph.svg").exists());
+}
+
+#[test]
+fn reads_piped_commands_and_fails_on_errors() {
+    let dir = scratch_dir("piped");
+    let graph = dir.join("graph.json");
+
+    let output = run_microdot(
+        &["--file", graph.to_str().unwrap()],
+        "i first\nnot a command\n",
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(std::fs::read_to_string(&graph).unwrap().contains("first"));
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let dir = scratch_dir("dry_run");
+    let graph = dir.join("graph.json");
+
+    let output = run_microdot(
+        &["--file", graph.to_str().unwrap(), "--dry-run"],
+        "i first\nj\n",
+    );
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("first"));
+    assert!(!graph.exists());
+    assert!(!dir.join("graph.svg").exists());
+}
+
+#[test]
+fn counts_failed_graph_commands_and_can_stop_early() {
+    let dir = scratch_dir("stop_on_error");
+    let graph = dir.join("graph.json");
+
+    let output = run_microdot(
+        &["--file", graph.to_str().unwrap(), "--stop-on-error"],
+        "i first\nd n7\ni second\n",
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("(node n7 not found)"));
+
+    let json = std::fs::read_to_string(&graph).unwrap();
+    assert!(json.contains("first"));
+    assert!(!json.contains("second"));
+}
+
+#[test]
+fn extracts_a_slice_into_a_new_file() {
+    let dir = scratch_dir("extract");
+    let graph = dir.join("graph.json");
+    let slice = dir.join("slice.json");
+
+    let output = run_microdot(
+        &["--file", graph.to_str().unwrap()],
+        &format!(
+            "i cause #sales\ni effect #sales\ni elsewhere\nl n0 n1\nl n1 n2\nextract #sales > {}\n",
+            slice.to_str().unwrap()
+        ),
+    );
+
+    assert!(output.status.success());
+    let json = std::fs::read_to_string(&slice).unwrap();
+    assert!(json.contains("cause #sales"));
+    assert!(json.contains("\"e0\""));
+    assert!(!json.contains("elsewhere"));
+    assert!(!json.contains("\"e1\""));
+    assert!(std::fs::read_to_string(&graph)
+        .unwrap()
+        .contains("elsewhere"));
+}
+
+#[test]
+fn filters_the_drawing_but_saves_everything() {
+    let dir = scratch_dir("filter");
+    let graph = dir.join("graph.json");
+
+    let output = run_microdot(
+        &["--file", graph.to_str().unwrap(), "--mermaid"],
+        "i cause #sales\ni effect #sales\ni elsewhere\nl n0 n1\nl n1 n2\nfilter #sales\n",
+    );
+
+    assert!(output.status.success());
+    let svg = std::fs::read_to_string(dir.join("graph.svg")).unwrap();
+    assert!(svg.contains("effect"));
+    assert!(!svg.contains("elsewhere"));
+    assert!(std::fs::read_to_string(&graph)
+        .unwrap()
+        .contains("elsewhere"));
+    let mermaid = std::fs::read_to_string(dir.join("graph.mmd")).unwrap();
+    assert!(mermaid.contains("elsewhere"));
+    assert!(mermaid.contains("n1 --> n2"));
+}
+
+#[test]
+fn picks_a_palette_from_the_user_directory() {
+    let dir = scratch_dir("palettes");
+    let graph = dir.join("graph.json");
+    let palettes = dir.join("palettes");
+    std::fs::create_dir_all(&palettes).unwrap();
+    std::fs::write(palettes.join("mine.txt"), "mine: #112233 #445566\n").unwrap();
+
+    let output = run_microdot(
+        &[
+            "--file",
+            graph.to_str().unwrap(),
+            "--palettes",
+            palettes.to_str().unwrap(),
+        ],
+        "i tagged #one\npalette mine\n",
+    );
+
+    assert!(output.status.success());
+    let json = std::fs::read_to_string(&graph).unwrap();
+    assert!(json.contains(r#""palette": "mine""#));
+    let svg = std::fs::read_to_string(dir.join("graph.svg")).unwrap();
+    assert!(svg.contains("#112233") || svg.contains("#445566"));
+
+    let output = run_microdot(
+        &[
+            "--file",
+            graph.to_str().unwrap(),
+            "--palettes",
+            palettes.to_str().unwrap(),
+        ],
+        "palette missing\ni still here\n",
+    );
+    // a refused palette is a failure, but the rest of the script still runs
+    assert_eq!(output.status.code(), Some(1));
+    let json = std::fs::read_to_string(&graph).unwrap();
+    assert!(json.contains("still here"));
+    assert!(json.contains(r#""palette": "mine""#));
+    // Khroma downloads are named after their file, which can have any name at all
+    std::fs::write(
+        palettes.join("my-palette.json"),
+        r##"{ "favorites": [{ "color": "#ABCDEF" }] }"##,
+    )
+    .unwrap();
+    let output = run_microdot(
+        &[
+            "--file",
+            graph.to_str().unwrap(),
+            "--palettes",
+            palettes.to_str().unwrap(),
+        ],
+        "palette my-palette\n",
+    );
+    assert!(output.status.success());
+    let json = std::fs::read_to_string(&graph).unwrap();
+    assert!(json.contains(r#""palette": "my-palette""#));
+}